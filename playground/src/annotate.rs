@@ -0,0 +1,464 @@
+//! Per-expression value annotation in the style of xmpfilter.
+//!
+//! Every line which ends in a `# =>` marker has the inspected value of the
+//! expression on that line filled in after the marker:
+//!
+//! ```ruby
+//! x = [1, 2, 3] # =>
+//! x.sum         # =>
+//! ```
+//!
+//! becomes:
+//!
+//! ```ruby
+//! x = [1, 2, 3] # => [1, 2, 3]
+//! x.sum         # => 6
+//! ```
+//!
+//! The source is instrumented so each marked expression records its value
+//! when it is evaluated. A line evaluated more than once records every value.
+//! Marked lines which are never evaluated are left untouched. If the code
+//! raises, the values recorded up to that point are filled in and the
+//! exception is appended as a trailing `# ~>` comment.
+//!
+//! A marker is only honored if the code in front of it is a complete
+//! statement of the whole program. Markers on lines which open or close a
+//! multi-line construct, like `xs.each do |x| # =>` or `end # =>`, continue an
+//! expression from the line before or onto the line after, or fall inside a
+//! string, heredoc, or comment are left untouched, so instrumenting never
+//! turns valid code into a syntax error or changes what the program does.
+
+use bstr::ByteSlice;
+
+use artichoke::prelude::*;
+
+use crate::prelude::Prelude;
+use crate::report::{self, Outcome, Report};
+use crate::syntax::{self, Severity};
+
+/// The marker which requests a line be annotated.
+const MARKER: &[u8] = b"# =>";
+
+/// Ruby source for the annotation prelude.
+const PRELUDE: Prelude = Prelude {
+    path: "playground/annotate.rb",
+    source: br#"
+module Playground
+  ANNOTATIONS = {}
+
+  def self.annotate(line, value)
+    (ANNOTATIONS[line] ||= []) << value.inspect
+    value
+  end
+
+  def self.annotation(line)
+    ANNOTATIONS[line]&.join(', ')
+  end
+end
+"#,
+};
+
+/// A `# =>` marker found in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Marker {
+    /// The 0-based index of the line the marker is on.
+    pub line: usize,
+    /// The byte offset of the marker within its line.
+    pub column: usize,
+}
+
+/// Find every line in `code` which ends in a `# =>` marker.
+///
+/// Lines which consist only of a comment are skipped. Markers found by this
+/// function may not be safe to instrument, see [`standalone_markers`].
+///
+/// # Examples
+///
+/// ```
+/// use playground::annotate::{markers, Marker};
+///
+/// let code = b"x = 1 # =>\n# =>\ny = 2\n";
+/// assert_eq!(markers(code), [Marker { line: 0, column: 6 }]);
+/// ```
+#[must_use]
+pub fn markers(code: &[u8]) -> Vec<Marker> {
+    code.lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let column = text.rfind(MARKER)?;
+            let expr = text[..column].trim_with(is_blank);
+            if expr.is_empty() || expr.starts_with(b"#") {
+                return None;
+            }
+            Some(Marker { line, column })
+        })
+        .collect()
+}
+
+/// Find every `# =>` marker in `code` whose expression is a complete
+/// statement.
+///
+/// Each candidate found by [`markers`] is checked by parsing the whole program
+/// on the given interpreter twice:
+///
+/// - With the marked line [instrumented](instrument) and surrounded by empty
+///   statements, the program must still parse. This rejects lines which open
+///   or close a multi-line construct, and lines whose expression continues
+///   from the line before or onto the line after.
+/// - With an unmatched `end` inserted before the marked line, the program
+///   must fail to parse. This rejects lines inside a string, heredoc, or
+///   comment, where any text parses.
+///
+/// The code is never evaluated.
+///
+/// # Errors
+///
+/// If the interpreter cannot be accessed, an error is returned.
+pub fn standalone_markers(interp: &mut Artichoke, code: &[u8]) -> Result<Vec<Marker>, Error> {
+    let lines = code.lines_with_terminator().collect::<Vec<_>>();
+    let mut standalone = Vec::new();
+    for marker in markers(code) {
+        let line = lines[marker.line];
+
+        let mut statement = b";\n".to_vec();
+        statement.extend_from_slice(&instrument_line(marker, line));
+        statement.extend_from_slice(b"\n;\n");
+        if !parses(interp, &replace_line(code, marker.line, &statement))? {
+            continue;
+        }
+
+        let mut unmatched = b"end\n".to_vec();
+        unmatched.extend_from_slice(line);
+        if parses(interp, &replace_line(code, marker.line, &unmatched))? {
+            continue;
+        }
+        standalone.push(marker);
+    }
+    Ok(standalone)
+}
+
+/// Rewrite `code` so the expression on every marked line records its value
+/// with `Playground.annotate`.
+///
+/// Unmarked lines are copied verbatim, so line numbers in the instrumented
+/// source match the original.
+#[must_use]
+pub fn instrument(code: &[u8], markers: &[Marker]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(code.len());
+    let mut markers = markers.iter().peekable();
+    for (idx, line) in code.lines_with_terminator().enumerate() {
+        let Some(&marker) = markers.next_if(|marker| marker.line == idx) else {
+            out.extend_from_slice(line);
+            continue;
+        };
+        out.extend_from_slice(&instrument_line(marker, line));
+        out.extend_from_slice(&line[line.trim_end_with(is_line_terminator).len()..]);
+    }
+    out
+}
+
+/// Wrap the expression in front of `marker` in a call to
+/// `Playground.annotate`, keeping its indentation and dropping the comment
+/// and line terminator.
+fn instrument_line(marker: Marker, line: &[u8]) -> Vec<u8> {
+    let text = &line[..marker.column];
+    let expr = text.trim_start_with(is_blank);
+    let indent = &text[..text.len() - expr.len()];
+    let mut out = indent.to_vec();
+    out.extend_from_slice(format!("Playground.annotate({}, (", marker.line).as_bytes());
+    out.extend_from_slice(expr.trim_end_with(is_blank));
+    out.extend_from_slice(b"))");
+    out
+}
+
+/// Fill in the marked lines of `code` with their recorded values.
+///
+/// `values` yields the annotation for each marker in `markers`, or [`None`]
+/// if the marked line was never evaluated.
+///
+/// # Examples
+///
+/// ```
+/// use playground::annotate::{markers, rewrite};
+///
+/// let code = b"x = 1 # =>\nx + 1 # => 5\n";
+/// let markers = markers(code);
+/// let values = [Some(b"1".to_vec()), Some(b"2".to_vec())];
+/// let annotated = rewrite(code, &markers, &values);
+/// assert_eq!(annotated, b"x = 1 # => 1\nx + 1 # => 2\n");
+/// ```
+#[must_use]
+pub fn rewrite(code: &[u8], markers: &[Marker], values: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(code.len());
+    let mut annotations = markers.iter().zip(values).peekable();
+    for (idx, line) in code.lines_with_terminator().enumerate() {
+        let Some((marker, value)) = annotations.next_if(|(marker, _)| marker.line == idx) else {
+            out.extend_from_slice(line);
+            continue;
+        };
+        let Some(value) = value else {
+            out.extend_from_slice(line);
+            continue;
+        };
+        out.extend_from_slice(&line[..marker.column]);
+        out.extend_from_slice(MARKER);
+        out.push(b' ');
+        // Keep each annotation on its own line.
+        out.extend(value.iter().map(|&b| if b == b'\n' { b' ' } else { b }));
+        out.extend_from_slice(&line[line.trim_end_with(is_line_terminator).len()..]);
+    }
+    out
+}
+
+/// Load the annotation prelude on the given interpreter, discard values
+/// recorded by a previous run, and return the markers in `code` to fill in.
+///
+/// Eval the [instrumented](instrument) source to record values for the
+/// returned markers, then collect them with [`values`].
+///
+/// # Errors
+///
+/// If the prelude fails to load or the interpreter cannot be accessed, an
+/// error is returned.
+pub fn prepare(interp: &mut Artichoke, code: &[u8]) -> Result<Vec<Marker>, Error> {
+    PRELUDE.load(interp)?;
+    let annotations = interp.eval(b"Playground::ANNOTATIONS")?;
+    annotations.funcall(interp, "clear", &[], None)?;
+    standalone_markers(interp, code)
+}
+
+/// Collect the values recorded for each of `markers` by an instrumented eval.
+///
+/// Markers on lines which were never evaluated have no value.
+///
+/// # Errors
+///
+/// If the recorded values cannot be extracted from the interpreter, an error
+/// is returned.
+pub fn values(interp: &mut Artichoke, markers: &[Marker]) -> Result<Vec<Option<Vec<u8>>>, Error> {
+    let playground = interp.eval(b"Playground")?;
+    let mut values = Vec::with_capacity(markers.len());
+    for marker in markers {
+        let line = interp.convert(i64::try_from(marker.line).unwrap_or(i64::MAX));
+        let value = playground.funcall(interp, "annotation", &[line], None)?;
+        if value.is_nil() {
+            values.push(None);
+        } else {
+            values.push(Some(value.try_convert_into_mut::<Vec<u8>>(interp)?));
+        }
+    }
+    Ok(values)
+}
+
+/// Append the output and outcome of an annotation eval to the annotated
+/// source as trailing comments.
+///
+/// Each line of stdout is appended as a `# >>` comment and each line of
+/// stderr as a `# !>` comment. An exception or timeout is appended as a
+/// `# ~>` comment.
+///
+/// # Examples
+///
+/// ```
+/// use playground::annotate::write_report;
+/// use playground::report::Report;
+///
+/// let report = Report {
+///     stdout: b"hello\n".to_vec(),
+///     ..Report::default()
+/// };
+/// let mut annotated = b"puts 'hello'".to_vec();
+/// write_report(&mut annotated, &report);
+/// assert_eq!(annotated, b"puts 'hello'\n# >> hello\n");
+/// ```
+pub fn write_report(annotated: &mut Vec<u8>, report: &Report) {
+    for line in report.stdout.lines() {
+        write_comment(annotated, b"# >> ", line);
+    }
+    for line in report.stderr.lines() {
+        write_comment(annotated, b"# !> ", line);
+    }
+    match report.outcome {
+        Outcome::Value(_) => {}
+        Outcome::Exception(ref exc) => {
+            let mut text = exc.class.as_bytes().to_vec();
+            text.extend_from_slice(b": ");
+            text.extend_from_slice(&exc.message);
+            write_comment(annotated, b"# ~> ", &text);
+        }
+        Outcome::Timeout(limit) => {
+            let mut text = String::from("Execution exceeded ");
+            // Writing to a `String` is infallible.
+            let _ = report::write_duration(&mut text, limit);
+            write_comment(annotated, b"# ~> ", text.as_bytes());
+        }
+    }
+}
+
+/// Append `text` to `annotated` as a comment on its own line.
+///
+/// Newlines in `text` are replaced with spaces.
+fn write_comment(annotated: &mut Vec<u8>, prefix: &[u8], text: &[u8]) {
+    if !annotated.is_empty() && !annotated.ends_with(b"\n") {
+        annotated.push(b'\n');
+    }
+    annotated.extend_from_slice(prefix);
+    annotated.extend(text.iter().map(|&b| if b == b'\n' { b' ' } else { b }));
+    annotated.push(b'\n');
+}
+
+/// Copy `code` with the line at `idx` replaced by `replacement`.
+fn replace_line(code: &[u8], idx: usize, replacement: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(code.len() + replacement.len());
+    for (line_idx, line) in code.lines_with_terminator().enumerate() {
+        if line_idx == idx {
+            out.extend_from_slice(replacement);
+            out.push(b'\n');
+        } else {
+            out.extend_from_slice(line);
+        }
+    }
+    out
+}
+
+/// Returns `true` if `code` parses without errors.
+fn parses(interp: &mut Artichoke, code: &[u8]) -> Result<bool, Error> {
+    let diagnostics = syntax::check(interp, code)?;
+    Ok(diagnostics
+        .iter()
+        .all(|diagnostic| diagnostic.severity != Severity::Error))
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn is_line_terminator(c: char) -> bool {
+    c == '\r' || c == '\n'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{instrument, markers, rewrite, standalone_markers, Marker};
+    use crate::interpreter::Interp;
+
+    #[test]
+    fn find_markers() {
+        let code = b"a = 1 # =>\n  # =>\nb = 2\nc = '# =>' # => old\n";
+        assert_eq!(
+            markers(code),
+            [
+                Marker { line: 0, column: 6 },
+                Marker {
+                    line: 3,
+                    column: 11
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn instrument_preserves_lines() {
+        let code = b"def f\n  1 + 1 # =>\nend\r\nf # =>";
+        let markers = markers(code);
+        let instrumented = instrument(code, &markers);
+        assert_eq!(
+            instrumented,
+            &b"def f\n  Playground.annotate(1, (1 + 1))\nend\r\nPlayground.annotate(3, (f))"[..]
+        );
+    }
+
+    #[test]
+    fn rewrite_skips_unevaluated_lines() {
+        let code = b"x = 1 # =>\ny = 2 # => stale\r\n";
+        let markers = markers(code);
+        let annotated = rewrite(code, &markers, &[Some(b"1".to_vec()), None]);
+        assert_eq!(annotated, &b"x = 1 # => 1\ny = 2 # => stale\r\n"[..]);
+    }
+
+    #[test]
+    fn annotate_values_and_exception() {
+        let mut interp = Interp::new().unwrap();
+        let code = b"xs = [1, 2] # =>\nxs.each do |x|\n  x * 10 # =>\nend\nraise 'boom'\nxs # =>\n";
+        let annotated = interp.annotate(code).unwrap();
+        let expected = b"xs = [1, 2] # => [1, 2]
+xs.each do |x|
+  x * 10 # => 10, 20
+end
+raise 'boom'
+xs # =>
+# ~> RuntimeError: boom
+";
+        assert_eq!(annotated, &expected[..]);
+    }
+
+    #[test]
+    fn standalone_markers_skip_partial_expressions() {
+        let mut interp = artichoke::interpreter().unwrap();
+        let code = b"def f # =>
+  xs = [1, 2]
+  xs.each do |x| # =>
+    x # =>
+  end # =>
+end # =>
+s = \"a # =>\"
+t = [1,
+  2] # =>
+f # =>
+";
+        let lines = standalone_markers(&mut interp, code)
+            .unwrap()
+            .iter()
+            .map(|marker| marker.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [3, 9]);
+        interp.close();
+
+        let mut interp = Interp::new().unwrap();
+        let annotated = interp.annotate(code).unwrap();
+        let expected = b"def f # =>
+  xs = [1, 2]
+  xs.each do |x| # =>
+    x # => 1, 2
+  end # =>
+end # =>
+s = \"a # =>\"
+t = [1,
+  2] # =>
+f # => [1, 2]
+";
+        assert_eq!(annotated, &expected[..]);
+    }
+
+    #[test]
+    fn standalone_markers_skip_continuations_and_strings() {
+        let mut interp = artichoke::interpreter().unwrap();
+        let code = b"x = 1 +
+  2 # =>
+s = <<~EOS
+  x # =>
+EOS
+=begin
+x # =>
+=end
+t = %(
+  x # =>
+)
+x # =>
+";
+        let lines = standalone_markers(&mut interp, code)
+            .unwrap()
+            .iter()
+            .map(|marker| marker.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [11]);
+        interp.close();
+
+        let mut interp = Interp::new().unwrap();
+        let annotated = interp.annotate(code).unwrap();
+        let mut expected = code.to_vec();
+        expected.truncate(expected.len() - b"x # =>\n".len());
+        expected.extend_from_slice(b"x # => 3\n");
+        assert_eq!(annotated, expected);
+    }
+}
@@ -0,0 +1,324 @@
+//! Interpretation of ANSI escape sequences in captured output.
+//!
+//! Ruby programs commonly colorize terminal output with SGR (Select Graphic
+//! Rendition) escape sequences like `"\e[31m"`. [`parse`] splits captured
+//! output into [`Span`]s of text with the [`Style`] in effect for each span so
+//! frontends can render colors instead of raw escape codes.
+//!
+//! Other CSI sequences, like cursor movement and screen clearing, have no
+//! meaning in the output pane and are stripped.
+
+use std::fmt;
+use std::mem;
+
+use crate::json;
+
+/// The escape byte which begins an escape sequence.
+const ESC: u8 = 0x1B;
+
+/// A foreground or background color.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Color {
+    /// An index into the terminal's 256 color palette.
+    ///
+    /// Indexes `0` through `7` are the standard colors set with SGR codes
+    /// `30`–`37` and `40`–`47`. Indexes `8` through `15` are the bright colors
+    /// set with SGR codes `90`–`97` and `100`–`107`.
+    Indexed(u8),
+    /// A 24-bit color.
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Serialize this color as JSON.
+    ///
+    /// Indexed colors are serialized as a number and 24-bit colors are
+    /// serialized as a `"#rrggbb"` string.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        match self {
+            Self::Indexed(idx) => write!(f, "{idx}"),
+            Self::Rgb(r, g, b) => write!(f, r##""#{r:02x}{g:02x}{b:02x}""##),
+        }
+    }
+}
+
+/// Text attributes set by SGR escape sequences.
+#[derive(Default, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Style {
+    /// The foreground color, or [`None`] for the default color.
+    pub fg: Option<Color>,
+    /// The background color, or [`None`] for the default color.
+    pub bg: Option<Color>,
+    /// Whether the text is bold.
+    pub bold: bool,
+    /// Whether the text is underlined.
+    pub underline: bool,
+}
+
+impl Style {
+    /// Apply the parameters of an SGR escape sequence to this style.
+    ///
+    /// Unknown parameters are ignored.
+    fn apply(&mut self, params: &[u8]) {
+        let mut codes = params
+            .split(|&b| b == b';' || b == b':')
+            .map(|param| {
+                param.iter().try_fold(0_u32, |code, &b| {
+                    let digit = char::from(b).to_digit(10)?;
+                    code.checked_mul(10)?.checked_add(digit)
+                })
+            })
+            .map(Option::unwrap_or_default);
+
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                4 => self.underline = true,
+                21 | 22 => self.bold = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(Color::Indexed((code - 30) as u8)),
+                38 => self.fg = extended_color(&mut codes).or(self.fg),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(Color::Indexed((code - 40) as u8)),
+                48 => self.bg = extended_color(&mut codes).or(self.bg),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(Color::Indexed((code - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(Color::Indexed((code - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Parse the parameters of a `38` or `48` extended color code.
+fn extended_color<I>(codes: &mut I) -> Option<Color>
+where
+    I: Iterator<Item = u32>,
+{
+    let mut component = || codes.next().and_then(|code| u8::try_from(code).ok());
+    match component()? {
+        5 => component().map(Color::Indexed),
+        2 => Some(Color::Rgb(component()?, component()?, component()?)),
+        _ => None,
+    }
+}
+
+/// A run of text rendered with a single style.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    /// The style of the text.
+    pub style: Style,
+    /// The text, with escape sequences removed.
+    pub text: Vec<u8>,
+}
+
+impl Span {
+    /// Serialize this span as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// { "text": "...", "fg": 1, "bg": null, "bold": false, "underline": false }
+    /// ```
+    ///
+    /// See [`Color::write_json`] for the representation of colors.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        f.write_str(r#"{"text":"#)?;
+        json::write_string(&mut f, &self.text)?;
+        f.write_str(r#","fg":"#)?;
+        write_color(&mut f, self.style.fg)?;
+        f.write_str(r#","bg":"#)?;
+        write_color(&mut f, self.style.bg)?;
+        write!(
+            f,
+            r#","bold":{},"underline":{}}}"#,
+            self.style.bold, self.style.underline
+        )
+    }
+}
+
+fn write_color<W>(mut f: W, color: Option<Color>) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    match color {
+        Some(color) => color.write_json(f),
+        None => f.write_str("null"),
+    }
+}
+
+/// Serialize a list of spans as a JSON array.
+///
+/// # Errors
+///
+/// If the provided writer returns an error, this function will return it.
+pub fn write_json<W>(mut f: W, spans: &[Span]) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    f.write_str("[")?;
+    for (idx, span) in spans.iter().enumerate() {
+        if idx > 0 {
+            f.write_str(",")?;
+        }
+        span.write_json(&mut f)?;
+    }
+    f.write_str("]")
+}
+
+/// Split `bytes` into styled spans by interpreting SGR escape sequences.
+///
+/// Escape sequences are removed from the text. Adjacent text with the same
+/// style is merged into a single span and empty spans are omitted.
+///
+/// # Examples
+///
+/// ```
+/// use playground::ansi::{parse, Color};
+///
+/// let spans = parse(b"\x1b[1;31merror:\x1b[0m oops");
+/// assert_eq!(spans.len(), 2);
+/// assert_eq!(spans[0].text, b"error:");
+/// assert_eq!(spans[0].style.fg, Some(Color::Indexed(1)));
+/// assert!(spans[0].style.bold);
+/// assert_eq!(spans[1].text, b" oops");
+/// assert_eq!(spans[1].style.fg, None);
+/// ```
+#[must_use]
+pub fn parse(bytes: &[u8]) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut style = Style::default();
+    let mut text = Vec::new();
+    let mut idx = 0;
+
+    while let Some(&byte) = bytes.get(idx) {
+        if byte != ESC {
+            text.push(byte);
+            idx += 1;
+            continue;
+        }
+        if bytes.get(idx + 1) != Some(&b'[') {
+            // Drop a stray escape byte.
+            idx += 1;
+            continue;
+        }
+        // A CSI sequence is `ESC [`, then parameter and intermediate bytes in
+        // `0x20..=0x3F`, then a final byte.
+        let start = idx + 2;
+        let params_len = bytes[start..]
+            .iter()
+            .take_while(|&&b| (0x20..=0x3F).contains(&b))
+            .count();
+        let end = start + params_len;
+        let Some(&final_byte) = bytes.get(end) else {
+            // Drop a truncated sequence.
+            break;
+        };
+        if final_byte == b'm' {
+            let mut next = style;
+            next.apply(&bytes[start..end]);
+            if next != style {
+                push_span(&mut spans, style, &mut text);
+                style = next;
+            }
+        }
+        idx = end + 1;
+    }
+    push_span(&mut spans, style, &mut text);
+    spans
+}
+
+fn push_span(spans: &mut Vec<Span>, style: Style, text: &mut Vec<u8>) {
+    if text.is_empty() {
+        return;
+    }
+    match spans.last_mut() {
+        Some(last) if last.style == style => last.text.append(text),
+        _ => spans.push(Span {
+            style,
+            text: mem::take(text),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, write_json, Color, Span, Style};
+
+    #[test]
+    fn plain_text_is_a_single_span() {
+        let spans = parse(b"hello\nworld\n");
+        assert_eq!(
+            spans,
+            [Span {
+                style: Style::default(),
+                text: b"hello\nworld\n".to_vec(),
+            }]
+        );
+        assert!(parse(b"").is_empty());
+    }
+
+    #[test]
+    fn basic_and_bright_colors() {
+        let spans = parse(b"\x1b[32mok\x1b[39m \x1b[44;97mbox\x1b[m");
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0].style.fg, Some(Color::Indexed(2)));
+        assert_eq!(spans[1].style, Style::default());
+        assert_eq!(spans[2].style.fg, Some(Color::Indexed(15)));
+        assert_eq!(spans[2].style.bg, Some(Color::Indexed(4)));
+    }
+
+    #[test]
+    fn extended_colors() {
+        let spans = parse(b"\x1b[38;5;208ma\x1b[48;2;16;32;255mb");
+        assert_eq!(spans[0].style.fg, Some(Color::Indexed(208)));
+        assert_eq!(spans[1].style.fg, Some(Color::Indexed(208)));
+        assert_eq!(spans[1].style.bg, Some(Color::Rgb(16, 32, 255)));
+    }
+
+    #[test]
+    fn bold_and_underline() {
+        let spans = parse(b"\x1b[1;4mboth\x1b[22monly underline\x1b[24mnone");
+        assert!(spans[0].style.bold && spans[0].style.underline);
+        assert!(!spans[1].style.bold && spans[1].style.underline);
+        assert_eq!(spans[2].style, Style::default());
+    }
+
+    #[test]
+    fn non_sgr_sequences_are_stripped_and_spans_merge() {
+        let spans = parse(b"a\x1b[2Kb\x1b[0mc\x1b[1");
+        assert_eq!(
+            spans,
+            [Span {
+                style: Style::default(),
+                text: b"abc".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn spans_json() {
+        let spans = parse(b"\x1b[1;38;2;255;0;0mred\x1b[0m!");
+        let mut s = String::new();
+        write_json(&mut s, &spans).unwrap();
+        assert_eq!(
+            s,
+            r##"[{"text":"red","fg":"#ff0000","bg":null,"bold":true,"underline":false},{"text":"!","fg":null,"bg":null,"bold":false,"underline":false}]"##
+        );
+    }
+}
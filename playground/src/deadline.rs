@@ -0,0 +1,146 @@
+//! Wall clock execution budgets enforced while Ruby code runs.
+//!
+//! [`arm`] installs a code fetch hook on the mruby VM which compares the clock
+//! against a deadline every few thousand instructions. The hook is shared with
+//! memory quotas. Once the deadline has
+//! passed, the hook raises `Playground::Timeout` from inside the VM, which
+//! unwinds the running eval the same way any other exception would. This
+//! interrupts code which never returns control to the host, like `loop {}`.
+//!
+//! `Playground::Timeout` inherits from `Exception`, so a bare `rescue` does
+//! not swallow it. Code which rescues `Exception` is interrupted again at the
+//! next check for as long as the deadline stays armed.
+//!
+//! The deadline is tracked per thread. Only one eval runs on a thread at a
+//! time, and the hook is only installed on the interpreter running it.
+//!
+//! mruby only has a code fetch hook when it is built with
+//! `MRB_USE_DEBUG_HOOK`. The workspace's `.cargo/config.toml` passes the define
+//! to both the C compiler and bindgen so `mrb_state` has the same layout on
+//! both sides of the FFI.
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use artichoke::prelude::*;
+
+use crate::hook;
+use crate::prelude::Prelude;
+
+/// Name of the exception class raised when an eval exceeds its budget.
+pub const TIMEOUT_CLASS: &str = "Playground::Timeout";
+
+/// Number of VM instructions executed between reads of the clock.
+const CHECK_INTERVAL: u32 = 4096;
+
+/// Ruby source for the deadline prelude.
+const PRELUDE: Prelude = Prelude {
+    path: "playground/deadline.rb",
+    source: br#"
+module Playground
+  # Raised from inside the VM when an eval runs past its execution budget.
+  class Timeout < Exception
+  end
+end
+"#,
+};
+
+thread_local! {
+    /// The instant the running eval must finish by, if any.
+    static DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+
+    /// Instructions executed since the deadline was armed.
+    static TICKS: Cell<u32> = Cell::new(0);
+}
+
+/// Interrupt Ruby code running on the given interpreter once `limit` has
+/// elapsed.
+///
+/// The budget applies to every eval until [`disarm`] is called.
+///
+/// # Errors
+///
+/// If the prelude fails to load or the interpreter cannot be accessed, an
+/// error is returned.
+pub fn arm(interp: &mut Artichoke, limit: Duration) -> Result<(), Error> {
+    PRELUDE.load(interp)?;
+    DEADLINE.with(|deadline| deadline.set(Instant::now().checked_add(limit)));
+    TICKS.with(|ticks| ticks.set(0));
+    hook::sync(interp)
+}
+
+/// Remove the execution budget installed by [`arm`].
+///
+/// # Errors
+///
+/// If the interpreter cannot be accessed, an error is returned.
+pub fn disarm(interp: &mut Artichoke) -> Result<(), Error> {
+    DEADLINE.with(|deadline| deadline.set(None));
+    hook::sync(interp)
+}
+
+/// Returns `true` if `err` is the exception raised when an eval runs past
+/// its budget.
+#[must_use]
+pub fn is_timeout(err: &Error) -> bool {
+    err.name() == TIMEOUT_CLASS
+}
+
+/// Returns `true` if a deadline is armed.
+pub(crate) fn is_armed() -> bool {
+    DEADLINE.with(Cell::get).is_some()
+}
+
+/// Returns `true` if a deadline is armed and has passed.
+#[must_use]
+pub fn expired() -> bool {
+    DEADLINE
+        .with(Cell::get)
+        .map_or(false, |deadline| Instant::now() >= deadline)
+}
+
+/// Returns `true` if the armed deadline has passed.
+///
+/// The clock is only read every [`CHECK_INTERVAL`] calls.
+pub(crate) fn check() -> bool {
+    let ticks = TICKS.with(|ticks| {
+        let next = ticks.get().wrapping_add(1);
+        ticks.set(next);
+        next
+    });
+    ticks % CHECK_INTERVAL == 0 && expired()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use artichoke::prelude::*;
+
+    use super::{arm, disarm, is_timeout};
+
+    #[test]
+    fn interrupts_code_which_never_returns() {
+        let mut interp = artichoke::interpreter().unwrap();
+        arm(&mut interp, Duration::from_millis(10)).unwrap();
+        let err = interp.eval(b"loop {}").unwrap_err();
+        assert!(is_timeout(&err));
+
+        let err = interp.eval(b"begin; loop {}; rescue; end").unwrap_err();
+        assert!(is_timeout(&err));
+        interp.close();
+    }
+
+    #[test]
+    fn disarmed_interpreter_runs_to_completion() {
+        let mut interp = artichoke::interpreter().unwrap();
+        arm(&mut interp, Duration::from_secs(60)).unwrap();
+        let value = interp.eval(b"(1..10_000).sum").unwrap();
+        assert_eq!(value.try_convert_into::<i64>(&interp).unwrap(), 50_005_000);
+
+        disarm(&mut interp).unwrap();
+        let value = interp.eval(b"Playground::Timeout.superclass").unwrap();
+        assert_eq!(value.inspect(&mut interp), b"Exception");
+        interp.close();
+    }
+}
@@ -0,0 +1,142 @@
+//! Reproducible evals with a fixed random seed and a frozen clock.
+//!
+//! Output which depends on `rand` or `Time.now` differs on every run, which
+//! makes shared playground links useless for bug reports. A deterministic
+//! eval seeds the default random number generator and pins `Time.now` to a
+//! fixed instant before running, and the report echoes both so the run can be
+//! reproduced exactly.
+
+use std::fmt;
+
+use artichoke::backend::value;
+use artichoke::prelude::*;
+
+use crate::prelude::Prelude;
+
+/// Ruby source for the determinism prelude.
+const PRELUDE: Prelude = Prelude {
+    path: "playground/determinism.rb",
+    source: br#"
+module Playground
+  module Clock
+    @now = nil
+
+    class << self
+      attr_accessor :now
+    end
+  end
+end
+
+class Time
+  class << self
+    alias_method :__playground_now, :now
+
+    def now
+      frozen = Playground::Clock.now
+      frozen.nil? ? __playground_now : at(frozen)
+    end
+  end
+end
+"#,
+};
+
+/// The inputs which make an eval reproducible.
+#[derive(Default, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Determinism {
+    /// Seed for `Random::DEFAULT`, as passed to `Random.srand`.
+    pub seed: i64,
+    /// The instant `Time.now` returns, in seconds since the Unix epoch.
+    pub now: i64,
+}
+
+impl Determinism {
+    /// Serialize these inputs as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// { "seed": 42, "now": 1700000000 }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        write!(f, r#"{{"seed":{},"now":{}}}"#, self.seed, self.now)
+    }
+}
+
+/// Install the determinism prelude on the given interpreter and apply
+/// `determinism` to subsequent code.
+///
+/// If `determinism` is [`None`], `Time.now` returns the real time again and,
+/// if the clock was previously frozen, the random number generator is
+/// reseeded from system entropy.
+///
+/// # Errors
+///
+/// If the prelude fails to load or the seed or clock cannot be set, an error
+/// is returned.
+pub fn apply(interp: &mut Artichoke, determinism: Option<Determinism>) -> Result<(), Error> {
+    PRELUDE.load(interp)?;
+
+    let clock = interp.eval(b"Playground::Clock")?;
+    let random = interp.eval(b"Random")?;
+    if let Some(determinism) = determinism {
+        let now = interp.convert(determinism.now);
+        clock.funcall(interp, "now=", &[now], None)?;
+        let seed = interp.convert(determinism.seed);
+        random.funcall(interp, "srand", &[seed], None)?;
+    } else {
+        let frozen = clock.funcall(interp, "now", &[], None)?;
+        if !frozen.is_nil() {
+            clock.funcall(interp, "now=", &[value::Value::nil()], None)?;
+            random.funcall(interp, "srand", &[], None)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use artichoke::prelude::*;
+
+    use super::{apply, Determinism};
+
+    #[test]
+    fn seed_and_clock_are_fixed() {
+        let mut interp = artichoke::interpreter().unwrap();
+        let determinism = Determinism {
+            seed: 42,
+            now: 1_700_000_000,
+        };
+
+        apply(&mut interp, Some(determinism)).unwrap();
+        let first = interp.eval(b"[rand(1_000_000), Time.now.to_i]").unwrap();
+        let first = first.inspect(&mut interp);
+
+        apply(&mut interp, Some(determinism)).unwrap();
+        let second = interp.eval(b"[rand(1_000_000), Time.now.to_i]").unwrap();
+        let second = second.inspect(&mut interp);
+
+        assert_eq!(first, second);
+        assert!(first.ends_with(b", 1700000000]"));
+
+        apply(&mut interp, None).unwrap();
+        let now = interp.eval(b"Time.now.to_i").unwrap();
+        let now = now.try_convert_into::<i64>(&interp).unwrap();
+        assert!(now > 1_700_000_000);
+        interp.close();
+    }
+
+    #[test]
+    fn json() {
+        let determinism = Determinism { seed: -7, now: 0 };
+        let mut s = String::new();
+        determinism.write_json(&mut s).unwrap();
+        assert_eq!(s, r#"{"seed":-7,"now":0}"#);
+    }
+}
@@ -1,120 +1,1621 @@
 //! FFI utilities for JavaScript / Rust interop over Wasm.
+//!
+//! Each playground instance is represented by a [`State`] which is stored in a
+//! thread local [`Registry`]. Foreign code refers to a state with the opaque,
+//! generation-checked handle returned by `artichoke_web_repl_init`, which
+//! allows several playgrounds on the same page to each own an isolated
+//! interpreter and string heap.
+//!
+//! Exports which are given a state handle that is stale, forged, or has been
+//! freed do not touch any state and instead return [`ERR_INVALID_STATE`].
+//! `artichoke_string_getch` returns a single byte and cannot represent that
+//! code, so it returns `0` instead, which is indistinguishable from a NUL byte.
+//! Callers read a string's length with `artichoke_string_getlen` first, which
+//! does report [`ERR_INVALID_STATE`], before reading its bytes.
+//!
+//! Exports are not re-entrant. A state stays borrowed for the duration of an
+//! export, including while Ruby code is evaluated, so calling another export
+//! from inside an eval, for example from a JavaScript callback, aborts the
+//! module.
 
-use std::mem::{size_of, ManuallyDrop};
+use std::cell::RefCell;
+use std::fmt::{self, Write as _};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::str;
+use std::time::Duration;
 
+use artichoke::prelude::Error;
+
+use crate::determinism::Determinism;
 use crate::interpreter::Interp;
-use crate::string::Heap;
+use crate::json;
+use crate::output::Callback;
+use crate::process::{self, Env};
+use crate::registry::Registry;
+use crate::report::{BinaryFormat, Layout, Outcome, Report, TextOptions};
+use crate::string::{Heap, HeapError};
+use crate::syntax;
+use crate::vfs::FileSystem;
+
+/// Status code returned by exports which completed successfully.
+pub const STATUS_OK: u32 = 0;
+
+/// Error code returned by exports when given a state handle which is stale,
+/// forged, or has been freed.
+///
+/// No valid state handle, string heap pointer, or string length is equal to
+/// this value.
+pub const ERR_INVALID_STATE: u32 = u32::MAX;
+
+/// Error code returned by the `artichoke_fs_*` exports when given a path which
+/// is not a valid relative path or is in the reserved `playground/` directory.
+pub const ERR_INVALID_PATH: u32 = u32::MAX - 1;
+
+/// Error code returned by the `artichoke_fs_*` exports when there is no file
+/// at the given path.
+pub const ERR_FILE_NOT_FOUND: u32 = u32::MAX - 2;
+
+/// Error code returned by [`artichoke_env_set`] when given a variable name
+/// which is empty or contains `=` or NUL bytes.
+pub const ERR_INVALID_ENV_NAME: u32 = u32::MAX - 3;
+
+/// Error code returned by [`artichoke_set_binary_format`] when given an
+/// unknown format.
+pub const ERR_INVALID_FORMAT: u32 = u32::MAX - 4;
+
+/// Error code returned by exports which allocate in the string heap when every
+/// slot in the heap is in use.
+///
+/// See [`Heap::try_allocate`].
+pub const ERR_HEAP_EXHAUSTED: u32 = u32::MAX - 5;
+
+/// Error code returned by the byte range exports when given a string heap
+/// pointer which has been freed or was never allocated.
+pub const ERR_INVALID_POINTER: u32 = u32::MAX - 6;
+
+/// Error code returned by the byte range exports when given a range which
+/// extends past the end of a string.
+pub const ERR_OUT_OF_BOUNDS: u32 = u32::MAX - 7;
+
+thread_local! {
+    /// Every live playground [`State`] keyed by the opaque handle passed to
+    /// foreign code.
+    static STATES: RefCell<Registry<State>> = RefCell::new(Registry::new());
+}
+
+/// Run `f` with the state identified by the given handle.
+///
+/// Returns [`None`] if `state` does not refer to a live state.
+///
+/// Every export which takes a state handle runs through this function, and
+/// unwinding out of an `extern "C"` export is undefined behavior, so a panic
+/// in `f` aborts the process instead of unwinding. This includes the panics
+/// raised by the string heap when [`Heap::set_panic_on_misuse`] is enabled.
+///
+/// The registry is borrowed until `f` returns, so calling `with_state` again
+/// from inside `f` aborts with a `BorrowMutError`.
+fn with_state<F, T>(state: u32, f: F) -> Option<T>
+where
+    F: FnOnce(&mut State) -> T,
+{
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        STATES.with(|states| states.borrow_mut().get_mut(state).map(f))
+    }));
+    // The panic message has already been printed by the panic hook.
+    result.unwrap_or_else(|_| std::process::abort())
+}
+
+/// Render a report, or the message of an eval which failed to produce one, as
+/// the text shown in the playground output pane.
+fn render_text(
+    report: Result<&Report, &str>,
+    options: TextOptions,
+    out: &mut String,
+) -> fmt::Result {
+    match report {
+        Ok(report) => report.write_text_with_options(out, options),
+        Err(message) => out.write_str(message),
+    }
+}
+
+/// Render a report as JSON.
+///
+/// JSON reports always include both the grouped output and the ordered
+/// transcript and escape invalid UTF-8, so text options are ignored. An eval
+/// which failed to produce a report is rendered as an object with a single
+/// `error` member. See [`json::write_error`].
+fn render_json(
+    report: Result<&Report, &str>,
+    _options: TextOptions,
+    out: &mut String,
+) -> fmt::Result {
+    match report {
+        Ok(report) => report.write_json(out),
+        Err(message) => json::write_error(out, message.as_bytes()),
+    }
+}
 
-/// String heap for marshalling data between Rust and JavaScript.
-#[derive(Default, Debug, Clone)]
+/// Ruby code to eval.
+#[derive(Debug)]
+enum Source {
+    /// Inline code read from the string heap.
+    Code(Vec<u8>),
+    /// Inline code read from the string heap which is located at `line` of
+    /// `filename` in a larger source file.
+    Located {
+        code: Vec<u8>,
+        filename: Vec<u8>,
+        line: u32,
+    },
+    /// Path to an entrypoint in the virtual filesystem.
+    File(String),
+}
+
+/// Store `s` in a new slot in the heap tagged with the export which allocated
+/// it.
+///
+/// Returns [`ERR_HEAP_EXHAUSTED`] if the heap has no free slots.
+fn allocate(heap: &mut Heap, tag: &'static str, s: String) -> u32 {
+    let Ok(ptr) = heap.try_allocate(s) else {
+        return ERR_HEAP_EXHAUSTED;
+    };
+    heap.set_tag(ptr, tag);
+    ptr
+}
+
+/// Copy `bytes`, which may not be UTF-8, into a new slot in the heap tagged
+/// with the export which allocated it.
+///
+/// Returns [`ERR_HEAP_EXHAUSTED`] if the heap has no free slots.
+fn allocate_bytes(heap: &mut Heap, tag: &'static str, bytes: Vec<u8>) -> u32 {
+    let Ok(ptr) = heap.try_allocate_bytes(bytes) else {
+        return ERR_HEAP_EXHAUSTED;
+    };
+    heap.set_tag(ptr, tag);
+    ptr
+}
+
+/// Convert a string heap error into the error code returned by exports.
+fn heap_error_code(err: HeapError) -> u32 {
+    match err {
+        HeapError::Stale | HeapError::Unallocated => ERR_INVALID_POINTER,
+        HeapError::OutOfBounds => ERR_OUT_OF_BOUNDS,
+        HeapError::Exhausted => ERR_HEAP_EXHAUSTED,
+    }
+}
+
+/// String heap and session interpreter for marshalling data between Rust and
+/// JavaScript.
+#[derive(Default, Debug)]
 pub struct State {
     /// The string heap.
     ///
     /// The `extern "C"` functions in this module use this heap to allow JS and
     /// Wasm code to pass strings back and forth across the Wasm boundary.
     heap: Heap,
+    /// A long-lived interpreter used by `artichoke_session_eval`.
+    ///
+    /// Unlike `artichoke_eval`, which creates a fresh interpreter for every
+    /// run, classes, constants, and globals defined on the session interpreter
+    /// persist across evals until the session is reset.
+    session: Option<Interp>,
+    /// Standard input to attach to the next eval.
+    ///
+    /// The buffer is consumed by the next eval. Evals without an attached
+    /// buffer read from an empty stdin.
+    stdin: Option<Vec<u8>>,
+    /// `ARGV` for the next eval.
+    ///
+    /// Like [`stdin`](Self::stdin), the arguments are consumed by the next
+    /// eval.
+    argv: Vec<Vec<u8>>,
+    /// `ENV` for the next eval.
+    ///
+    /// The variables are consumed by the next eval. Every eval sees exactly
+    /// these variables, so evals on the session interpreter cannot leak
+    /// variables into later runs.
+    env: Env,
+    /// Wall clock execution budget applied to every eval.
+    time_limit: Option<Duration>,
+    /// Memory quota, in bytes, applied to every eval.
+    memory_limit: Option<usize>,
+    /// Host function which receives output while every eval is running.
+    output_callback: Option<Callback>,
+    /// Random seed and frozen clock applied to every eval.
+    determinism: Option<Determinism>,
+    /// Rendering options for text reports.
+    text: TextOptions,
+    /// Virtual filesystem mounted on the interpreter for every eval.
+    ///
+    /// Files written or deleted by Ruby code are synced back once the eval
+    /// completes.
+    files: FileSystem,
 }
 
 impl State {
-    /// Convert a boxed state into an opaque, pointer-sized value to pass to
-    /// foreign code.
-    #[must_use]
-    pub fn into_raw(self: Box<Self>) -> u32 {
-        Box::into_raw(self) as u32
+    /// Retrieve the session interpreter, initializing a new one if the session
+    /// has not yet been started or has been reset.
+    ///
+    /// # Errors
+    ///
+    /// If the session interpreter fails to initialize, an error is returned.
+    /// See [`Interp::new`].
+    pub fn session(&mut self) -> Result<&mut Interp, Error> {
+        let interp = match self.session.take() {
+            Some(interp) if !interp.is_closed() => interp,
+            _ => Interp::new()?,
+        };
+        Ok(self.session.insert(interp))
     }
 
-    /// Convert an opaque, pointer-sized value back to a boxed state.
+    /// Close the session interpreter, if any.
     ///
-    /// `from_raw` operates on values returned from [`into_raw`].
+    /// The next call to [`session`](Self::session) will construct a new
+    /// interpreter with fresh state.
+    pub fn reset_session(&mut self) {
+        // Dropping `Interp` closes the underlying Artichoke interpreter.
+        self.session = None;
+    }
+
+    /// Eval the code stored in the heap at `ptr` and store the rendered report
+    /// in the heap.
+    ///
+    /// If `session` is `true`, the code is evaluated on the long-lived session
+    /// interpreter, otherwise a fresh interpreter is created for this eval.
+    ///
+    /// Returns a heap pointer to the rendered report.
+    fn eval<F>(&mut self, ptr: u32, session: bool, render: F) -> u32
+    where
+        F: Fn(Result<&Report, &str>, TextOptions, &mut String) -> fmt::Result,
+    {
+        let code = self.heap.string(ptr).to_vec();
+        self.run(Source::Code(code), session, render)
+    }
+
+    /// Eval the code stored in the heap at `ptr` as if it were located at
+    /// `line` of the file named by the string stored in the heap at
+    /// `filename`, and store the rendered report in the heap.
+    ///
+    /// See [`eval`](Self::eval) and [`Interp::eval_at`].
+    fn eval_at<F>(&mut self, ptr: u32, filename: u32, line: u32, session: bool, render: F) -> u32
+    where
+        F: Fn(Result<&Report, &str>, TextOptions, &mut String) -> fmt::Result,
+    {
+        let source = Source::Located {
+            code: self.heap.string(ptr).to_vec(),
+            filename: self.heap.string(filename).to_vec(),
+            line,
+        };
+        self.run(source, session, render)
+    }
+
+    /// Run the file in the virtual filesystem whose path is stored in the heap
+    /// at `ptr` and store the rendered report in the heap.
+    ///
+    /// See [`eval`](Self::eval).
+    fn eval_file<F>(&mut self, ptr: u32, session: bool, render: F) -> u32
+    where
+        F: Fn(Result<&Report, &str>, TextOptions, &mut String) -> fmt::Result,
+    {
+        let path = String::from_utf8_lossy(self.heap.string(ptr)).into_owned();
+        self.run(Source::File(path), session, render)
+    }
+
+    /// Annotate the code stored in the heap at `ptr` and store the annotated
+    /// source in the heap.
+    ///
+    /// The interpreter is configured the same way as for [`eval`](Self::eval).
+    /// See [`Interp::annotate`].
+    ///
+    /// Returns a heap pointer to the annotated source.
+    fn annotate(&mut self, ptr: u32, session: bool) -> u32 {
+        let code = self.heap.string(ptr).to_vec();
+        let annotated = self.with_interp(session, |interp| {
+            interp.annotate(&code).map_err(|err| err.to_string())
+        });
+        match annotated {
+            Ok(source) => allocate_bytes(&mut self.heap, "annotate", source),
+            Err(message) => allocate(&mut self.heap, "annotate", message),
+        }
+    }
+
+    /// Attach `input` as standard input for the next eval.
     ///
-    /// [`into_raw`]: Self::into_raw
+    /// The buffer is consumed by the next eval.
+    pub fn set_stdin(&mut self, input: Vec<u8>) {
+        self.stdin = Some(input);
+    }
+
+    /// Eval `code` on a fresh interpreter, configured the same way as for
+    /// `artichoke_eval`, and render the report as the text shown in the
+    /// playground output pane.
+    ///
+    /// Native builds of the playground binary use this to print the same
+    /// output as the browser.
     ///
-    /// # Safety
+    /// # Errors
+    ///
+    /// If the eval raised an exception, ran past its time limit, or could not
+    /// run at all, the rendered report is returned as an error.
+    pub fn eval_text(&mut self, code: &[u8]) -> Result<String, String> {
+        let report = self.report(Source::Code(code.to_vec()), false);
+        let text = self.render_report(&report, render_text);
+        match report {
+            Ok(Report {
+                outcome: Outcome::Value(_),
+                ..
+            }) => Ok(text),
+            _ => Err(text),
+        }
+    }
+
+    fn run<F>(&mut self, source: Source, session: bool, render: F) -> u32
+    where
+        F: Fn(Result<&Report, &str>, TextOptions, &mut String) -> fmt::Result,
+    {
+        let report = self.report(source, session);
+        let out = self.render_report(&report, render);
+        allocate(&mut self.heap, "eval", out)
+    }
+
+    /// Eval `source` and build its structured report.
     ///
-    /// `raw` must be the address of a `Box<State>` which was returned from
-    /// `State::into_raw`. The state represented by this raw address must not
-    /// have been dropped.
+    /// See [`with_interp`](Self::with_interp).
+    fn report(&mut self, source: Source, session: bool) -> Result<Report, String> {
+        self.with_interp(session, |interp| {
+            let report = match source {
+                Source::Code(code) => interp.eval_to_structured_report(&code),
+                Source::Located {
+                    code,
+                    filename,
+                    line,
+                } => interp.eval_at_to_structured_report(&code, &filename, line),
+                Source::File(path) => interp.eval_file_to_structured_report(Path::new(&path)),
+            };
+            report.ok_or_else(|| String::from("Fatal error"))
+        })
+    }
+
+    /// Render the result of [`report`](Self::report) with the configured
+    /// text options.
+    fn render_report<F>(&self, report: &Result<Report, String>, render: F) -> String
+    where
+        F: Fn(Result<&Report, &str>, TextOptions, &mut String) -> fmt::Result,
+    {
+        let mut out = String::new();
+        if render(report.as_ref().map_err(String::as_str), self.text, &mut out).is_err() {
+            out.clear();
+            // Rendering a message into a `String` cannot fail.
+            let _ = render(Err("Fatal error"), self.text, &mut out);
+        }
+        out
+    }
+
+    /// Run `f` on an interpreter configured with the stdin, `ARGV`, `ENV`,
+    /// limits, and virtual filesystem for the next eval.
     ///
-    /// # Panics
+    /// If `session` is `true`, `f` runs on the long-lived session interpreter,
+    /// otherwise a fresh interpreter is created. Files written or deleted by
+    /// Ruby code are synced back to the state once `f` returns.
     ///
-    /// This function panics if the given `raw` is 0 (a null pointer).
-    #[must_use]
-    pub unsafe fn from_raw(raw: u32) -> Box<Self> {
-        // ensure all `u32` can be converted to a pointer-sized value.
-        const _: () = assert!(size_of::<u32>() <= size_of::<*mut State>());
+    /// If the interpreter cannot be created or configured, the error message
+    /// is returned.
+    fn with_interp<F, T>(&mut self, session: bool, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Interp) -> Result<T, String>,
+    {
+        let stdin = self.stdin.take().unwrap_or_default();
+        let argv = mem::take(&mut self.argv);
+        let env = mem::take(&mut self.env);
+        let time_limit = self.time_limit;
+        let memory_limit = self.memory_limit;
+        let output_callback = self.output_callback;
+        let determinism = self.determinism;
+        let files = self.files.clone();
+        let mut synced = None;
+
+        let mut fresh = None;
+        let interp = if session {
+            self.session()
+        } else {
+            Interp::new().map(|interp| fresh.insert(interp))
+        };
+
+        let result = match interp {
+            Ok(interp) => {
+                interp.set_time_limit(time_limit);
+                interp.set_memory_limit(memory_limit);
+                interp.set_output_callback(output_callback);
+                interp.set_determinism(determinism);
+                let setup = interp
+                    .set_stdin(&stdin)
+                    .and_then(|()| interp.set_process(&argv, &env))
+                    .and_then(|()| interp.mount(&files));
+                match setup {
+                    Ok(()) => {
+                        let result = f(interp);
+                        synced = interp.files().ok();
+                        result
+                    }
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        if let Some(files) = synced {
+            self.files = files;
+        }
+        result
+    }
 
-        assert_ne!(raw, 0, "null pointer");
-        unsafe { Box::from_raw(raw as *mut State) }
+    /// Free every slot in the string heap and close the session interpreter.
+    ///
+    /// This function is idempotent.
+    pub fn close(&mut self) {
+        self.heap.clear();
+        self.reset_session();
+        self.stdin = None;
+        self.argv.clear();
+        self.env.clear();
+        self.files.clear();
     }
 }
 
 #[no_mangle]
 #[must_use]
 extern "C" fn artichoke_web_repl_init() -> u32 {
-    let mut state = Box::<State>::default();
-    let build = match Interp::new() {
-        Ok(mut interp) => interp
+    let mut state = State::default();
+    let build = match state.session() {
+        Ok(interp) => interp
             .metadata()
             .unwrap_or_else(|| String::from("Could not extract interpreter metadata")),
         Err(err) => err.to_string(),
     };
     println!("{build}");
     let sym = state.heap.allocate(build);
+    state.heap.set_tag(sym, "build_info");
     assert_eq!(sym, 0); // assumed by TypeScript code
-    state.into_raw()
+    STATES
+        .with(|states| states.borrow_mut().insert(state))
+        .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_web_repl_free(state: u32) -> u32 {
+    // Remove the state from the registry before closing it so the registry is
+    // not borrowed while the interpreter is torn down.
+    let state = STATES.with(|states| states.borrow_mut().remove(state));
+    if let Some(mut state) = state {
+        state.close();
+        STATUS_OK
+    } else {
+        ERR_INVALID_STATE
+    }
 }
 
 #[no_mangle]
 #[must_use]
 extern "C" fn artichoke_string_new(state: u32) -> u32 {
-    let state = unsafe { State::from_raw(state) };
-    let mut state = ManuallyDrop::new(state);
-    state.heap.allocate(String::new())
+    with_state(state, |state| {
+        allocate(&mut state.heap, "string_new", String::new())
+    })
+    .unwrap_or(ERR_INVALID_STATE)
 }
 
 #[no_mangle]
-extern "C" fn artichoke_string_free(state: u32, ptr: u32) {
-    let state = unsafe { State::from_raw(state) };
-    let mut state = ManuallyDrop::new(state);
-    state.heap.free(ptr);
+extern "C" fn artichoke_string_free(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| {
+        state.heap.free(ptr);
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
 }
 
 #[no_mangle]
 #[must_use]
 extern "C" fn artichoke_string_getlen(state: u32, ptr: u32) -> u32 {
-    let state = unsafe { State::from_raw(state) };
-    let state = ManuallyDrop::new(state);
-    state.heap.string_getlen(ptr)
+    with_state(state, |state| state.heap.string_getlen(ptr)).unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_string_try_getlen(state: u32, ptr: u32) -> u32 {
+    // Unlike `artichoke_string_getlen`, a freed or forged pointer is reported
+    // instead of reading as an empty string.
+    with_state(state, |state| match state.heap.try_string_getlen(ptr) {
+        Ok(len) => len,
+        Err(err) => heap_error_code(err),
+    })
+    .unwrap_or(ERR_INVALID_STATE)
 }
 
 #[no_mangle]
 #[must_use]
 extern "C" fn artichoke_string_getch(state: u32, ptr: u32, idx: u32) -> u8 {
-    let state = unsafe { State::from_raw(state) };
-    let state = ManuallyDrop::new(state);
-    state.heap.string_getch(ptr, idx)
+    // An invalid state reads as a NUL byte. Callers detect it with
+    // `artichoke_string_getlen` before reading any bytes.
+    with_state(state, |state| state.heap.string_getch(ptr, idx)).unwrap_or_default()
+}
+
+#[no_mangle]
+extern "C" fn artichoke_string_putch(state: u32, ptr: u32, ch: u8) -> u32 {
+    with_state(state, |state| {
+        state.heap.string_putch(ptr, ch);
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
 }
 
 #[no_mangle]
-extern "C" fn artichoke_string_putch(state: u32, ptr: u32, ch: u8) {
-    let state = unsafe { State::from_raw(state) };
-    let mut state = ManuallyDrop::new(state);
-    state.heap.string_putch(ptr, ch);
+#[must_use]
+extern "C" fn artichoke_string_reserve(state: u32, len: u32) -> u32 {
+    let len = usize::try_from(len).unwrap_or_default();
+    with_state(state, |state| match state.heap.try_reserve(len) {
+        Ok(ptr) => {
+            state.heap.set_tag(ptr, "string_reserve");
+            ptr
+        }
+        Err(_) => ERR_HEAP_EXHAUSTED,
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+// Addresses only fit in the `u32` return value on 32-bit targets like Wasm.
+#[cfg(target_pointer_width = "32")]
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_string_ptr(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| {
+        if state.heap.try_string(ptr).is_err() {
+            return ERR_INVALID_POINTER;
+        }
+        state.heap.string_as_mut_ptr(ptr) as u32
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_string_slice(state: u32, ptr: u32, offset: u32, len: u32) -> u32 {
+    let (offset, len) = (offset as usize, len as usize);
+    with_state(state, |state| {
+        match state.heap.string_slice(ptr, offset, len) {
+            Ok(bytes) => {
+                let bytes = bytes.to_vec();
+                allocate_bytes(&mut state.heap, "string_slice", bytes)
+            }
+            Err(err) => heap_error_code(err),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_string_write_at(state: u32, ptr: u32, offset: u32, src: u32) -> u32 {
+    with_state(state, |state| {
+        let bytes = match state.heap.try_string(src) {
+            Ok(bytes) => bytes.to_vec(),
+            Err(err) => return heap_error_code(err),
+        };
+        match state.heap.string_write_at(ptr, offset as usize, &bytes) {
+            Ok(()) => STATUS_OK,
+            Err(err) => heap_error_code(err),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_string_truncate(state: u32, ptr: u32, len: u32) -> u32 {
+    with_state(state, |state| {
+        match state.heap.string_truncate(ptr, len as usize) {
+            Ok(()) => STATUS_OK,
+            Err(err) => heap_error_code(err),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_string_extend(state: u32, ptr: u32, src: u32) -> u32 {
+    with_state(state, |state| {
+        let bytes = match state.heap.try_string(src) {
+            Ok(bytes) => bytes.to_vec(),
+            Err(err) => return heap_error_code(err),
+        };
+        match state.heap.string_extend(ptr, &bytes) {
+            Ok(()) => STATUS_OK,
+            Err(err) => heap_error_code(err),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
 }
 
 #[no_mangle]
 #[must_use]
 extern "C" fn artichoke_eval(state: u32, ptr: u32) -> u32 {
-    let state = unsafe { State::from_raw(state) };
-    let mut state = ManuallyDrop::new(state);
-    let code = state.heap.string(ptr);
-
-    let out = match Interp::new() {
-        Ok(mut interp) => interp
-            .eval_to_report(code)
-            .unwrap_or_else(|| String::from("Fatal error")),
-        Err(err) => err.to_string(),
+    with_state(state, |state| state.eval(ptr, false, render_text)).unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_session_eval(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| state.eval(ptr, true, render_text)).unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_eval_at(state: u32, ptr: u32, filename: u32, line: u32) -> u32 {
+    with_state(state, |state| {
+        state.eval_at(ptr, filename, line, false, render_text)
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_session_eval_at(state: u32, ptr: u32, filename: u32, line: u32) -> u32 {
+    with_state(state, |state| {
+        state.eval_at(ptr, filename, line, true, render_text)
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_session_reset(state: u32) -> u32 {
+    with_state(state, |state| {
+        state.reset_session();
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_eval_json(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| state.eval(ptr, false, render_json)).unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_annotate(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| state.annotate(ptr, false)).unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_session_annotate(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| state.annotate(ptr, true)).unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_check_syntax(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| {
+        let code = state.heap.string(ptr).to_vec();
+        // Parsing does not run any code, so the session interpreter is reused
+        // rather than paying for a fresh interpreter on every keystroke.
+        let diagnostics = state
+            .session()
+            .and_then(|interp| interp.check_syntax(&code));
+        // If the source cannot be checked, report the failure as a JSON error
+        // object rather than a diagnostic at a made up position.
+        let mut out = String::new();
+        let written = match diagnostics {
+            Ok(diagnostics) => syntax::write_json(&mut out, &diagnostics),
+            Err(err) => json::write_error(&mut out, err.to_string().as_bytes()),
+        };
+        match written {
+            Ok(()) => allocate(&mut state.heap, "check_syntax", out),
+            Err(_) => allocate(&mut state.heap, "check_syntax", String::from("[]")),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_stdin(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| {
+        state.set_stdin(state.heap.string(ptr).to_vec());
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_argv_push(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| {
+        let arg = state.heap.string(ptr).to_vec();
+        state.argv.push(arg);
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_env_set(state: u32, name: u32, value: u32) -> u32 {
+    with_state(state, |state| {
+        let name = state.heap.string(name).to_vec();
+        if !process::is_valid_env_name(&name) {
+            return ERR_INVALID_ENV_NAME;
+        }
+        let value = state.heap.string(value).to_vec();
+        state.env.insert(name, value);
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_time_limit(state: u32, millis: u32) -> u32 {
+    with_state(state, |state| {
+        // A limit of zero removes the execution budget.
+        state.time_limit = Some(millis)
+            .filter(|&millis| millis > 0)
+            .map(|millis| Duration::from_millis(millis.into()));
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_memory_limit(state: u32, bytes: u32) -> u32 {
+    with_state(state, |state| {
+        // A limit of zero removes the memory quota.
+        state.memory_limit = Some(bytes)
+            .filter(|&bytes| bytes > 0)
+            .and_then(|bytes| usize::try_from(bytes).ok());
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_determinism(state: u32, enabled: u32, seed: u32, now: u32) -> u32 {
+    with_state(state, |state| {
+        // `now` is in seconds since the Unix epoch.
+        state.determinism = Some(Determinism {
+            seed: seed.into(),
+            now: now.into(),
+        })
+        .filter(|_| enabled != 0);
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_heap_debug(state: u32, enabled: u32) -> u32 {
+    with_state(state, |state| {
+        state.heap.set_panic_on_misuse(enabled != 0);
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_output_callback(state: u32, callback: Option<Callback>) -> u32 {
+    with_state(state, |state| {
+        // A null function pointer stops streaming.
+        state.output_callback = callback;
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_output_interleaved(state: u32, enabled: u32) -> u32 {
+    with_state(state, |state| {
+        state.text.layout = if enabled == 0 {
+            Layout::Grouped
+        } else {
+            Layout::Interleaved
+        };
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_set_binary_format(state: u32, format: u32) -> u32 {
+    let format = match format {
+        0 => BinaryFormat::Escape,
+        1 => BinaryFormat::Replace,
+        2 => BinaryFormat::HexDump,
+        _ => return ERR_INVALID_FORMAT,
     };
+    with_state(state, |state| {
+        state.text.binary = format;
+        STATUS_OK
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_eval_file(state: u32, ptr: u32) -> u32 {
+    with_state(state, |state| state.eval_file(ptr, false, render_text)).unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_fs_write(state: u32, path: u32, contents: u32) -> u32 {
+    with_state(state, |state| {
+        let Ok(path) = str::from_utf8(state.heap.string(path)) else {
+            return ERR_INVALID_PATH;
+        };
+        let contents = state.heap.string(contents).to_vec();
+        match state.files.write(path, contents) {
+            Some(()) => STATUS_OK,
+            None => ERR_INVALID_PATH,
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_fs_read(state: u32, path: u32) -> u32 {
+    with_state(state, |state| {
+        let Ok(path) = str::from_utf8(state.heap.string(path)) else {
+            return ERR_INVALID_PATH;
+        };
+        let Some(contents) = state.files.read(path) else {
+            return ERR_FILE_NOT_FOUND;
+        };
+        let contents = contents.to_vec();
+        allocate_bytes(&mut state.heap, "fs_read", contents)
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+extern "C" fn artichoke_fs_delete(state: u32, path: u32) -> u32 {
+    with_state(state, |state| {
+        let Ok(path) = str::from_utf8(state.heap.string(path)) else {
+            return ERR_INVALID_PATH;
+        };
+        match state.files.remove(path) {
+            Some(_) => STATUS_OK,
+            None => ERR_FILE_NOT_FOUND,
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_fs_list(state: u32) -> u32 {
+    with_state(state, |state| {
+        let mut out = String::new();
+        match json::write_string_array(&mut out, state.files.paths()) {
+            Ok(()) => allocate(&mut state.heap, "fs_list", out),
+            Err(_) => allocate(&mut state.heap, "fs_list", String::from("[]")),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_heap_stats(state: u32) -> u32 {
+    with_state(state, |state| {
+        // The snapshot is taken before the returned string is allocated.
+        let mut out = String::new();
+        match state.heap.stats().write_json(&mut out) {
+            Ok(()) => allocate(&mut state.heap, "heap_stats", out),
+            Err(_) => allocate(&mut state.heap, "heap_stats", String::from("{}")),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+#[no_mangle]
+#[must_use]
+extern "C" fn artichoke_heap_leaks(state: u32) -> u32 {
+    with_state(state, |state| {
+        let mut out = String::new();
+        match write_leaks(&mut out, &state.heap) {
+            Ok(()) => allocate(&mut state.heap, "heap_leaks", out),
+            Err(_) => allocate(&mut state.heap, "heap_leaks", String::from("[]")),
+        }
+    })
+    .unwrap_or(ERR_INVALID_STATE)
+}
+
+/// Serialize every live slot in the heap other than the build info slot as a
+/// JSON array.
+///
+/// The build info allocated by [`artichoke_web_repl_init`] is never freed, so
+/// every other live slot is a string foreign code has not yet freed. See
+/// [`LiveSlot::write_json`] for the shape of each slot.
+///
+/// [`LiveSlot::write_json`]: crate::string::LiveSlot::write_json
+fn write_leaks<W>(mut f: W, heap: &Heap) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    f.write_str("[")?;
+    let leaks = heap.live_slots().into_iter().filter(|slot| slot.ptr != 0);
+    for (idx, slot) in leaks.enumerate() {
+        if idx > 0 {
+            f.write_str(",")?;
+        }
+        slot.write_json(&mut f)?;
+    }
+    f.write_str("]")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::slice;
+    use std::time::Duration;
+
+    use crate::interpreter::MAX_LINE;
+    use crate::report::{Layout, TextOptions};
+
+    use super::{
+        artichoke_annotate, artichoke_check_syntax, artichoke_eval, artichoke_fs_delete,
+        artichoke_fs_read, artichoke_fs_write, artichoke_heap_leaks, artichoke_heap_stats,
+        artichoke_session_eval, artichoke_session_reset, artichoke_set_binary_format,
+        artichoke_set_determinism, artichoke_set_output_callback, artichoke_string_extend,
+        artichoke_string_free, artichoke_string_getch, artichoke_string_getlen,
+        artichoke_string_new, artichoke_string_putch, artichoke_string_slice,
+        artichoke_string_truncate, artichoke_string_try_getlen, artichoke_string_write_at,
+        artichoke_web_repl_free, artichoke_web_repl_init, render_json, render_text, with_state,
+        State, ERR_FILE_NOT_FOUND, ERR_INVALID_FORMAT, ERR_INVALID_PATH, ERR_INVALID_POINTER,
+        ERR_INVALID_STATE, ERR_OUT_OF_BOUNDS, STATUS_OK,
+    };
+
+    /// Drives the FFI exports the same way `src/interpreter.ts` does.
+    struct Harness {
+        state: u32,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let state = artichoke_web_repl_init();
+            assert_ne!(state, ERR_INVALID_STATE);
+            Self { state }
+        }
+
+        fn read(&self, ptr: u32) -> String {
+            let len = artichoke_string_getlen(self.state, ptr);
+            let bytes = (0..len)
+                .map(|idx| artichoke_string_getch(self.state, ptr, idx))
+                .collect::<Vec<_>>();
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+
+        fn write(&self, s: &str) -> u32 {
+            let ptr = artichoke_string_new(self.state);
+            for &byte in s.as_bytes() {
+                assert_eq!(artichoke_string_putch(self.state, ptr, byte), STATUS_OK);
+            }
+            ptr
+        }
+
+        fn eval_ruby(&self, source: &str) -> String {
+            let code = self.write(source);
+            let output = artichoke_eval(self.state, code);
+            let result = self.read(output);
+            assert_eq!(artichoke_string_free(self.state, code), STATUS_OK);
+            assert_eq!(artichoke_string_free(self.state, output), STATUS_OK);
+            result
+        }
+
+        fn live_slots(&self) -> usize {
+            with_state(self.state, |state| state.heap.len()).unwrap()
+        }
+    }
+
+    impl Drop for Harness {
+        fn drop(&mut self) {
+            assert_eq!(artichoke_web_repl_free(self.state), STATUS_OK);
+        }
+    }
+
+    #[test]
+    fn harness_build_info_is_slot_zero() {
+        let harness = Harness::new();
+        let build = harness.read(0);
+        assert!(!build.is_empty());
+        assert_eq!(harness.live_slots(), 1);
+
+        // Every state has its own heap.
+        let other = Harness::new();
+        assert_ne!(other.state, harness.state);
+        assert_eq!(other.read(0), build);
+    }
+
+    #[test]
+    fn harness_eval_frees_every_slot_it_allocates() {
+        let harness = Harness::new();
+        assert_eq!(harness.eval_ruby("puts 'hi'\n1 + 1"), "hi\n=> 2\n");
+        assert_eq!(harness.eval_ruby("'café'"), "=> \"café\"\n");
+        assert_eq!(harness.live_slots(), 1);
+    }
+
+    #[test]
+    fn harness_try_getlen_reports_invalid_pointers() {
+        let harness = Harness::new();
+        let ptr = harness.write("abc");
+        assert_eq!(artichoke_string_try_getlen(harness.state, ptr), 3);
+        assert_eq!(artichoke_string_free(harness.state, ptr), STATUS_OK);
+        assert_eq!(
+            artichoke_string_try_getlen(harness.state, ptr),
+            ERR_INVALID_POINTER
+        );
+        assert_eq!(
+            artichoke_string_try_getlen(harness.state, 0x00ff_ffff),
+            ERR_INVALID_POINTER
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "32")]
+    fn harness_string_ptr_reports_invalid_pointers() {
+        let harness = Harness::new();
+        let ptr = harness.write("abc");
+        assert_ne!(
+            super::artichoke_string_ptr(harness.state, ptr),
+            ERR_INVALID_POINTER
+        );
+        assert_eq!(artichoke_string_free(harness.state, ptr), STATUS_OK);
+        assert_eq!(
+            super::artichoke_string_ptr(harness.state, ptr),
+            ERR_INVALID_POINTER
+        );
+    }
+
+    #[test]
+    fn harness_leak_check_lists_unfreed_strings() {
+        let harness = Harness::new();
+        let leaks = artichoke_heap_leaks(harness.state);
+        assert_eq!(harness.read(leaks), "[]");
+        assert_eq!(artichoke_string_free(harness.state, leaks), STATUS_OK);
+
+        let code = harness.write("1 + 1");
+        let output = artichoke_eval(harness.state, code);
+        assert_eq!(artichoke_string_free(harness.state, code), STATUS_OK);
+
+        let leaks = artichoke_heap_leaks(harness.state);
+        assert_eq!(
+            harness.read(leaks),
+            format!(r#"[{{"ptr":{output},"len":5,"tag":"eval"}}]"#)
+        );
+        assert_eq!(artichoke_string_free(harness.state, leaks), STATUS_OK);
+        assert_eq!(artichoke_string_free(harness.state, output), STATUS_OK);
+
+        let stats = artichoke_heap_stats(harness.state);
+        let json = harness.read(stats);
+        assert!(json.starts_with(r#"{"live":1,"#), "{json}");
+        assert!(json.contains(r#""peak_live":3,"#), "{json}");
+        assert_eq!(artichoke_string_free(harness.state, stats), STATUS_OK);
+    }
+
+    #[test]
+    fn harness_binary_data_round_trips() {
+        let harness = Harness::new();
+        let path = harness.write("data.bin");
+        let code = harness.write(r#"File.write('data.bin', File.read('data.bin').reverse)"#);
+        let contents = artichoke_string_new(harness.state);
+        for byte in [0x80, 0x00, 0xFE] {
+            assert_eq!(
+                artichoke_string_putch(harness.state, contents, byte),
+                STATUS_OK
+            );
+        }
+        let tail = artichoke_string_slice(harness.state, contents, 1, 2);
+        assert_eq!(
+            artichoke_string_extend(harness.state, contents, tail),
+            STATUS_OK
+        );
+        assert_eq!(artichoke_fs_write(harness.state, path, contents), STATUS_OK);
+
+        let output = artichoke_eval(harness.state, code);
+        let read = artichoke_fs_read(harness.state, path);
+        let bytes = (0..artichoke_string_getlen(harness.state, read))
+            .map(|idx| artichoke_string_getch(harness.state, read, idx))
+            .collect::<Vec<_>>();
+        assert_eq!(bytes, [0xFE, 0x00, 0xFE, 0x00, 0x80]);
+
+        for ptr in [path, code, contents, tail, output, read] {
+            assert_eq!(artichoke_string_free(harness.state, ptr), STATUS_OK);
+        }
+        assert_eq!(harness.live_slots(), 1);
+    }
+
+    #[test]
+    fn harness_byte_range_errors() {
+        let harness = Harness::new();
+        let ptr = harness.write("abc");
+        let src = harness.write("xy");
+        assert_eq!(
+            artichoke_string_slice(harness.state, ptr, 2, 2),
+            ERR_OUT_OF_BOUNDS
+        );
+        assert_eq!(
+            artichoke_string_write_at(harness.state, ptr, 2, src),
+            ERR_OUT_OF_BOUNDS
+        );
+        assert_eq!(harness.read(ptr), "abc");
+
+        assert_eq!(artichoke_string_free(harness.state, src), STATUS_OK);
+        assert_eq!(
+            artichoke_string_extend(harness.state, ptr, src),
+            ERR_INVALID_POINTER
+        );
+        assert_eq!(artichoke_string_free(harness.state, ptr), STATUS_OK);
+        assert_eq!(
+            artichoke_string_truncate(harness.state, ptr, 0),
+            ERR_INVALID_POINTER
+        );
+    }
+
+    #[test]
+    fn harness_freed_state_rejects_every_call() {
+        let state = artichoke_web_repl_init();
+        let code = artichoke_string_new(state);
+        assert_eq!(artichoke_web_repl_free(state), STATUS_OK);
+
+        assert_eq!(artichoke_web_repl_free(state), ERR_INVALID_STATE);
+        assert_eq!(artichoke_string_new(state), ERR_INVALID_STATE);
+        assert_eq!(artichoke_string_putch(state, code, b'1'), ERR_INVALID_STATE);
+        assert_eq!(artichoke_string_getlen(state, 0), ERR_INVALID_STATE);
+        // `getch` cannot return `ERR_INVALID_STATE` in a byte, so an invalid
+        // state reads as NUL. The `getlen` check above is what callers rely on.
+        assert_eq!(artichoke_string_getch(state, 0, 0), 0);
+        assert_eq!(artichoke_string_free(state, code), ERR_INVALID_STATE);
+        assert_eq!(artichoke_eval(state, code), ERR_INVALID_STATE);
+    }
+
+    #[test]
+    fn web_repl_free_releases_only_the_given_state() {
+        let first = artichoke_web_repl_init();
+        let second = artichoke_web_repl_init();
+        assert_eq!(artichoke_web_repl_free(first), STATUS_OK);
+
+        // A state initialized after the free may reuse the freed slot, but the
+        // stale handle must not release it.
+        let third = artichoke_web_repl_init();
+        assert_ne!(third, first);
+        assert_eq!(artichoke_web_repl_free(first), ERR_INVALID_STATE);
+        assert_ne!(artichoke_string_getlen(third, 0), ERR_INVALID_STATE);
+        assert_ne!(artichoke_string_getlen(second, 0), ERR_INVALID_STATE);
+
+        assert_eq!(artichoke_web_repl_free(second), STATUS_OK);
+        assert_eq!(artichoke_web_repl_free(third), STATUS_OK);
+        assert_eq!(artichoke_web_repl_free(third), ERR_INVALID_STATE);
+    }
+
+    #[test]
+    fn close_frees_heap_and_session() {
+        let mut state = State::default();
+        state.session().unwrap();
+        let _ = state.heap.allocate(String::from("build info"));
+        let _ = state.heap.allocate(String::from("puts 'hello'"));
+
+        state.close();
+        assert!(state.heap.is_empty());
+        assert!(state.session.is_none());
+    }
+
+    #[test]
+    fn close_is_idempotent() {
+        let mut state = State::default();
+        let _ = state.heap.allocate(String::from("build info"));
+
+        state.close();
+        state.close();
+        assert!(state.heap.is_empty());
+        assert!(state.session.is_none());
+    }
+
+    #[test]
+    fn stdin_is_attached_to_next_eval() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from("gets.to_i + gets.to_i"));
+
+        state.stdin = Some(b"1\n2\n".to_vec());
+        let out = state.eval(code, false, render_text);
+        assert_eq!(state.heap.string(out), b"=> 3\n");
 
-    state.heap.allocate(out)
+        // The buffer is consumed by the eval.
+        let out = state.eval(code, false, render_text);
+        assert_eq!(state.heap.string(out), b"=> 0\n");
+    }
+
+    #[test]
+    fn eval_text_fails_on_exceptions() {
+        let mut state = State::default();
+        state.set_stdin(b"Ada\n".to_vec());
+        let out = state.eval_text(b"puts \"Hello, #{gets.chomp}\"");
+        assert_eq!(out.as_deref(), Ok("Hello, Ada\n=> nil\n"));
+
+        let out = state.eval_text(b"raise 'boom'").unwrap_err();
+        assert!(out.starts_with("RuntimeError (boom)"), "{out}");
+    }
+
+    #[test]
+    fn argv_and_env_are_sandboxed_per_eval() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from(
+            "ENV['LEAK'] = 'x'; ARGV.join(',') + ENV['NAME'].to_s",
+        ));
+
+        state.argv = vec![b"a".to_vec(), b"b".to_vec()];
+        state.env.insert(b"NAME".to_vec(), b"!".to_vec());
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"=> \"a,b!\"\n");
+
+        let leak = state
+            .heap
+            .allocate(String::from("[ARGV, ENV['NAME'], ENV['LEAK']]"));
+        let out = state.eval(leak, true, render_text);
+        assert_eq!(state.heap.string(out), b"=> [[], nil, nil]\n");
+    }
+
+    #[test]
+    fn annotate_fills_in_markers() {
+        let harness = Harness::new();
+        let code = harness.write("1 + 1 # =>\n");
+        let out = artichoke_annotate(harness.state, code);
+        assert_eq!(harness.read(out), "1 + 1 # => 2\n");
+    }
+
+    #[test]
+    fn annotate_reads_stdin_and_keeps_output() {
+        let mut state = State::default();
+        state.stdin = Some(b"hi\n".to_vec());
+        let code = state
+            .heap
+            .allocate(String::from("line = gets # =>\nputs line\nwarn 'w'"));
+
+        let out = state.annotate(code, false);
+        assert_eq!(
+            state.heap.string(out),
+            b"line = gets # => \"hi\\n\"\nputs line\nwarn 'w'\n# >> hi\n# !> w\n"
+        );
+    }
+
+    #[test]
+    fn check_syntax_does_not_eval() {
+        let harness = Harness::new();
+        let code = harness.write("puts 'hi'\nputs(");
+        let out = artichoke_check_syntax(harness.state, code);
+        assert!(harness.read(out).starts_with(r#"[{"line":"#));
+
+        // Checking shares the session interpreter with `artichoke_session_eval`,
+        // so a side effect of the checked code would be visible to later evals.
+        let code = harness.write("$x = 1");
+        let out = artichoke_check_syntax(harness.state, code);
+        assert_eq!(harness.read(out), "[]");
+        let code = harness.write("$x");
+        let out = artichoke_session_eval(harness.state, code);
+        assert_eq!(harness.read(out), "=> nil\n");
+    }
+
+    #[test]
+    fn eval_at_reports_caller_location() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from("[__FILE__, __LINE__]"));
+        let filename = state.heap.allocate(String::from("docs/intro.rb"));
+
+        let out = state.eval_at(code, filename, 42, true, render_text);
+        assert_eq!(state.heap.string(out), b"=> [\"docs/intro.rb\", 42]\n");
+
+        // The location does not leak into later evals on the same
+        // interpreter.
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"=> [\"(playground)\", 1]\n");
+    }
+
+    #[test]
+    fn eval_at_rejects_lines_past_max() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from("__LINE__"));
+        let filename = state.heap.allocate(String::from("docs/intro.rb"));
+
+        let out = state.eval_at(code, filename, MAX_LINE, false, render_text);
+        assert_eq!(state.heap.string(out), b"=> 65535\n");
+
+        let out = state.eval_at(code, filename, u32::MAX, false, render_text);
+        assert!(state.heap.string(out).starts_with(b"ArgumentError ("));
+    }
+
+    #[test]
+    fn eval_at_rejects_reserved_filenames() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from("raise 'boom'"));
+        let filename = state.heap.allocate(String::from("playground/stdin.rb"));
+
+        let out = state.eval_at(code, filename, 1, false, render_text);
+        assert!(state.heap.string(out).starts_with(b"ArgumentError ("));
+    }
+
+    #[test]
+    fn eval_at_backtrace_uses_caller_location() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from("x = 1\nraise 'boom'"));
+        let filename = state.heap.allocate(String::from("docs/intro.rb"));
+
+        let out = state.eval_at(code, filename, 10, false, render_json);
+        let json = String::from_utf8(state.heap.string(out).to_vec()).unwrap();
+        assert!(json.contains("docs/intro.rb:11"), "{json}");
+    }
+
+    #[test]
+    fn failed_evals_render_as_json_errors() {
+        let options = TextOptions::default();
+        let mut out = String::new();
+        render_json(Err("Fatal error"), options, &mut out).unwrap();
+        assert_eq!(out, r#"{"error":"Fatal error"}"#);
+
+        let mut out = String::new();
+        render_text(Err("Fatal error"), options, &mut out).unwrap();
+        assert_eq!(out, "Fatal error");
+    }
+
+    #[test]
+    fn time_limit_interrupts_runaway_eval() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from("loop {}"));
+
+        state.time_limit = Some(Duration::from_millis(10));
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"Execution exceeded 10ms\n");
+
+        // Evals which finish within the budget report their result, and the
+        // session survives the interrupted eval.
+        let code = state.heap.allocate(String::from("1 + 1"));
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"=> 2\n");
+        assert!(!state.session.as_ref().unwrap().is_closed());
+    }
+
+    #[test]
+    fn time_limit_applies_while_inspecting_the_result() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from(
+            "o = Object.new\ndef o.inspect\n  loop {}\nend\no",
+        ));
+
+        state.time_limit = Some(Duration::from_millis(10));
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"Execution exceeded 10ms\n");
+    }
+
+    #[test]
+    fn output_written_while_inspecting_belongs_to_its_eval() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from(
+            "o = Object.new\ndef o.inspect\n  puts 'inspecting'\n  'o'\nend\no",
+        ));
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"inspecting\n=> o\n");
+
+        let code = state.heap.allocate(String::from("1"));
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"=> 1\n");
+    }
+
+    #[test]
+    fn memory_limit_raises_no_memory_error() {
+        let mut state = State::default();
+        let code = state.heap.allocate(String::from("'x' * 1_000_000"));
+
+        state.memory_limit = Some(64 * 1024);
+        let out = state.eval(code, true, render_text);
+        assert!(state.heap.string(out).starts_with(b"NoMemoryError ("));
+
+        // The quota applies to each eval, and the session survives.
+        let code = state.heap.allocate(String::from("'x' * 10"));
+        let out = state.eval(code, true, render_text);
+        assert_eq!(state.heap.string(out), b"=> \"xxxxxxxxxx\"\n");
+        assert!(!state.session.as_ref().unwrap().is_closed());
+    }
+
+    #[test]
+    fn determinism_is_reproducible_and_echoed() {
+        let state = artichoke_web_repl_init();
+        assert_eq!(
+            artichoke_set_determinism(state, 1, 42, 1_700_000_000),
+            STATUS_OK
+        );
+        let run = || {
+            with_state(state, |state| {
+                let code = state
+                    .heap
+                    .allocate(String::from("[rand(1_000_000), Time.now.to_i]"));
+                let out = state.eval(code, true, render_json);
+                String::from_utf8(state.heap.string(out).to_vec()).unwrap()
+            })
+            .unwrap()
+        };
+        let first = run();
+        assert_eq!(first, run());
+        assert!(first.contains(", 1700000000]"), "{first}");
+        assert!(
+            first.ends_with(r#""determinism":{"seed":42,"now":1700000000}}"#),
+            "{first}"
+        );
+
+        assert_eq!(artichoke_set_determinism(state, 0, 0, 0), STATUS_OK);
+        let json = run();
+        assert!(json.ends_with(r#""determinism":null}"#), "{json}");
+        artichoke_web_repl_free(state);
+    }
+
+    #[test]
+    fn require_relative_loads_virtual_files() {
+        let mut state = State::default();
+        state
+            .files
+            .write("lib/greeting.rb", b"GREETING = 'hello'".to_vec())
+            .unwrap();
+        state
+            .files
+            .write(
+                "main.rb",
+                b"require_relative 'lib/greeting'\nFile.write('out.txt', GREETING)".to_vec(),
+            )
+            .unwrap();
+
+        let path = state.heap.allocate(String::from("main.rb"));
+        let out = state.eval_file(path, false, render_text);
+        assert_eq!(state.heap.string(out), b"=> 5\n");
+        assert_eq!(state.files.read("out.txt"), Some(&b"hello"[..]));
+    }
+
+    #[test]
+    fn eval_file_missing_raises_load_error() {
+        let mut state = State::default();
+        let path = state.heap.allocate(String::from("main.rb"));
+        let out = state.eval_file(path, false, render_text);
+        assert!(state.heap.string(out).starts_with(b"LoadError ("));
+    }
+
+    #[test]
+    fn fs_exports_reject_invalid_paths() {
+        let harness = Harness::new();
+        let state = harness.state;
+        let path = harness.write("../main.rb");
+        let contents = harness.write("");
+        assert_eq!(artichoke_fs_write(state, path, contents), ERR_INVALID_PATH);
+        assert_eq!(artichoke_fs_delete(state, path), ERR_INVALID_PATH);
+
+        let path = harness.write("main.rb");
+        assert_eq!(artichoke_fs_read(state, path), ERR_FILE_NOT_FOUND);
+        assert_eq!(artichoke_fs_write(state, path, contents), STATUS_OK);
+        assert_eq!(artichoke_fs_delete(state, path), STATUS_OK);
+        assert_eq!(artichoke_fs_delete(state, path), ERR_FILE_NOT_FOUND);
+    }
+
+    #[test]
+    fn interleaved_layout_preserves_write_order() {
+        let mut state = State::default();
+        let code = state
+            .heap
+            .allocate(String::from("warn 'a'; puts 'b'; warn 'c'; nil"));
+
+        let out = state.eval(code, false, render_text);
+        assert_eq!(state.heap.string(out), b"b\n--- stderr:a\nc\n=> nil\n");
+
+        state.text.layout = Layout::Interleaved;
+        let out = state.eval(code, false, render_text);
+        assert_eq!(
+            state.heap.string(out),
+            b"--- stderr:a\n--- stdout:b\n--- stderr:c\n=> nil\n"
+        );
+    }
+
+    #[test]
+    fn output_callback_receives_writes_while_the_eval_runs() {
+        thread_local! {
+            static STREAMED: RefCell<Vec<(u32, Vec<u8>)>> = RefCell::new(Vec::new());
+        }
+        extern "C" fn collect(fd: u32, bytes: *const u8, len: usize) {
+            // SAFETY: Callbacks are passed a pointer to `len` readable bytes.
+            let bytes = unsafe { slice::from_raw_parts(bytes, len) }.to_vec();
+            STREAMED.with(|streamed| streamed.borrow_mut().push((fd, bytes)));
+        }
+
+        let state = artichoke_web_repl_init();
+        assert_eq!(
+            artichoke_set_output_callback(state, Some(collect)),
+            STATUS_OK
+        );
+        let out = with_state(state, |state| {
+            let code = state
+                .heap
+                .allocate(String::from("puts 'a'; warn 'b'; puts 'c'; nil"));
+            let out = state.eval(code, false, render_text);
+            state.heap.string(out).to_vec()
+        })
+        .unwrap();
+        // Delivering the output only once the eval returned would merge the
+        // writes to stdout into a single chunk.
+        let streamed = STREAMED.with(RefCell::take);
+        let stdout = streamed
+            .iter()
+            .filter(|(fd, _)| *fd == 1)
+            .map(|(_, bytes)| bytes.as_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(stdout, [&b"a\n"[..], &b"c\n"[..]]);
+        let stderr = streamed
+            .iter()
+            .filter(|(fd, _)| *fd == 2)
+            .flat_map(|(_, bytes)| bytes.iter().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(stderr, b"b\n");
+        assert_eq!(streamed.first().unwrap().1, b"a\n");
+        assert_eq!(streamed.last().unwrap().1, b"c\n");
+        // Streamed output is still part of the report.
+        assert_eq!(out, b"a\nc\n--- stderr:b\n=> nil\n");
+
+        assert_eq!(artichoke_set_output_callback(state, None), STATUS_OK);
+        with_state(state, |state| {
+            let code = state.heap.allocate(String::from("puts 'quiet'"));
+            let _ = state.eval(code, false, render_text);
+        });
+        assert!(STREAMED.with(|streamed| streamed.borrow().is_empty()));
+        assert_eq!(artichoke_web_repl_free(state), STATUS_OK);
+        assert_eq!(
+            artichoke_set_output_callback(state, Some(collect)),
+            ERR_INVALID_STATE
+        );
+    }
+
+    #[test]
+    fn binary_format_applies_to_next_eval() {
+        let harness = Harness::new();
+        let state = harness.state;
+        let code = harness.write(r#"print "\xFF\n"; nil"#);
+        assert_eq!(artichoke_set_binary_format(state, 3), ERR_INVALID_FORMAT);
+
+        let out = artichoke_eval(state, code);
+        assert_eq!(harness.read(out), "\\xFF\n=> nil\n");
+
+        assert_eq!(artichoke_set_binary_format(state, 1), STATUS_OK);
+        let out = artichoke_eval(state, code);
+        assert_eq!(harness.read(out), "\u{FFFD}\n=> nil\n");
+    }
+
+    #[test]
+    fn free_is_idempotent() {
+        let state = artichoke_web_repl_init();
+        assert_eq!(artichoke_web_repl_free(state), STATUS_OK);
+        assert_eq!(artichoke_web_repl_free(state), ERR_INVALID_STATE);
+    }
+
+    #[test]
+    fn instances_are_isolated() {
+        let a = artichoke_web_repl_init();
+        let b = artichoke_web_repl_init();
+        assert_ne!(a, b);
+
+        let ptr = artichoke_string_new(a);
+        assert_eq!(artichoke_string_getlen(a, ptr), 0);
+        assert_eq!(artichoke_web_repl_free(a), STATUS_OK);
+
+        // `b` is unaffected by freeing `a`.
+        assert_ne!(artichoke_string_getlen(b, 0), ERR_INVALID_STATE);
+        assert_eq!(artichoke_web_repl_free(b), STATUS_OK);
+    }
+
+    #[test]
+    fn stale_and_forged_handles_are_rejected() {
+        let state = artichoke_web_repl_init();
+        assert_eq!(artichoke_web_repl_free(state), STATUS_OK);
+
+        assert_eq!(artichoke_string_new(state), ERR_INVALID_STATE);
+        assert_eq!(artichoke_string_getlen(state, 0), ERR_INVALID_STATE);
+        assert_eq!(artichoke_session_reset(state), ERR_INVALID_STATE);
+
+        assert_eq!(artichoke_string_new(0), ERR_INVALID_STATE);
+        assert_eq!(artichoke_string_new(0xDEAD_BEEF), ERR_INVALID_STATE);
+    }
 }
@@ -0,0 +1,58 @@
+//! The mruby code fetch hook shared by the features which act on running Ruby
+//! code.
+//!
+//! mruby calls the hook before it executes each VM instruction. Execution
+//! budgets and memory quotas both need to interrupt code which never returns
+//! control to the host, and output streaming needs to deliver output before
+//! the eval returns, so they share a single hook. [`sync`] installs it on an
+//! interpreter while any of them is armed and removes it once all are
+//! disarmed.
+
+use artichoke::backend::sys;
+use artichoke::prelude::*;
+
+use crate::{deadline, output, quota};
+
+/// Install the code fetch hook if a deadline, memory quota, or output stream
+/// is armed, and remove it otherwise.
+///
+/// # Errors
+///
+/// If the interpreter cannot be accessed, an error is returned.
+pub fn sync(interp: &mut Artichoke) -> Result<(), Error> {
+    let armed = deadline::is_armed() || quota::is_armed() || output::is_armed();
+    // SAFETY: The hook only reads thread locals and the interpreter state,
+    // calls the output callback, runs the garbage collector, and raises
+    // through the VM, so it is sound to call from any instruction.
+    // Clearing the hook restores the VM's default behavior.
+    unsafe {
+        interp.with_ffi_boundary(|mrb| {
+            (*mrb).code_fetch_hook = if armed { Some(on_code_fetch) } else { None };
+        })?;
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn on_code_fetch(
+    mrb: *mut sys::mrb_state,
+    _irep: *const sys::mrb_irep,
+    _pc: *const sys::mrb_code,
+    _regs: *mut sys::mrb_value,
+) {
+    // SAFETY: `mrb_raise` unwinds to the VM's exception handler with
+    // `longjmp`. Jumping over a Rust frame is sound as long as the frame owns
+    // no values with destructors, which holds here because this frame only
+    // holds pointers borrowed from the VM. Artichoke raises from the native
+    // methods it defines the same way.
+    unsafe {
+        output::flush(mrb);
+        if quota::exceeded(mrb) {
+            let class = sys::mrb_class_get(mrb, b"NoMemoryError\0".as_ptr().cast());
+            sys::mrb_raise(mrb, class, b"memory quota exceeded\0".as_ptr().cast());
+        } else if deadline::check() {
+            let module = sys::mrb_module_get(mrb, b"Playground\0".as_ptr().cast());
+            let class = sys::mrb_class_get_under(mrb, module, b"Timeout\0".as_ptr().cast());
+            sys::mrb_raise(mrb, class, b"execution expired\0".as_ptr().cast());
+        }
+    }
+}
@@ -4,18 +4,33 @@ use std::ffi::OsStr;
 use std::fmt;
 use std::mem;
 use std::path::Path;
-use std::str;
-
-use bstr::ByteSlice;
+use std::time::{Duration, Instant};
 
 use artichoke::backend::ffi::InterpreterExtractError;
 use artichoke::backend::state::output::Captured;
 use artichoke::backend::state::parser::Context;
 use artichoke::backend::value;
 use artichoke::prelude::*;
-use scolapasta_string_escape::format_debug_escape_into;
 
+use crate::annotate;
+use crate::deadline;
+use crate::determinism::{self, Determinism};
 use crate::meta;
+use crate::output;
+use crate::prelude;
+use crate::process::{self, Env};
+use crate::quota;
+use crate::report::{ExceptionReport, Outcome, Report, TextOptions, ValueReport};
+use crate::stdin;
+use crate::syntax::{self, Diagnostic};
+use crate::transcript;
+use crate::vfs::{self, FileSystem};
+
+/// The largest starting line accepted by [`Interp::eval_at`].
+///
+/// mruby's parser context tracks the line it is parsing as a 16-bit integer,
+/// so code cannot be placed past this line.
+pub const MAX_LINE: u32 = u16::MAX as u32;
 
 /// Convert a Ruby interpreter invocation into a displayable report.
 ///
@@ -27,62 +42,108 @@ pub struct Reporter<T> {
     pub result: Result<T, Error>,
     /// The captured stdout and stderr of the interpreter.
     pub output: Captured,
+    /// Wall clock time spent in [`Artichoke::eval`].
+    pub duration: Duration,
 }
 
 impl<T> Reporter<T>
 where
-    T: Value<Artichoke = Artichoke>,
+    T: Value<Artichoke = Artichoke, Value = T>,
 {
+    /// Extract the structured [`Report`] for this eval.
+    ///
+    /// Both the text and JSON reports are derived from this data model.
+    pub fn report(&self, interp: &mut Artichoke) -> Report {
+        let outcome = self.outcome(interp);
+        self.report_with_outcome(interp, outcome)
+    }
+
+    /// Describe the value returned or the exception raised by this eval.
+    ///
+    /// Describing a returned value calls its `inspect` and `class` methods,
+    /// which may run arbitrary Ruby code and write to stdout or stderr.
+    pub fn outcome(&self, interp: &mut Artichoke) -> Outcome {
+        match self.result {
+            Ok(ref value) => {
+                let inspect = value.inspect(interp);
+                let class = value
+                    .funcall(interp, "class", &[], None)
+                    .map(|class| class.inspect(interp))
+                    .unwrap_or_default();
+                Outcome::Value(ValueReport { inspect, class })
+            }
+            Err(ref exc) => Outcome::Exception(ExceptionReport::new(
+                exc.name().into_owned(),
+                exc.message().into_owned(),
+                exc.vm_backtrace(interp).unwrap_or_default(),
+            )),
+        }
+    }
+
+    /// Extract the structured [`Report`] for this eval given an `outcome`
+    /// previously built with [`outcome`](Self::outcome).
+    pub fn report_with_outcome(&self, interp: &mut Artichoke, outcome: Outcome) -> Report {
+        let Self {
+            output, duration, ..
+        } = self;
+
+        Report {
+            stdout: output.stdout().to_vec(),
+            stderr: output.stderr().to_vec(),
+            transcript: transcript::collect(interp, output.stdout(), output.stderr()),
+            outcome,
+            duration: *duration,
+            determinism: None,
+        }
+    }
+
     /// Coalesce stdout, stderr, and `returned_value.inspect` into an output
     /// report suitable for displaying in the playground webapp.
     ///
+    /// See [`Report::write_text`] for more details.
+    ///
     /// # Errors
     ///
     /// If the provided writer returns an error, this function will return it.
-    pub fn to_report<W>(&self, mut f: W, interp: &mut Artichoke) -> fmt::Result
+    pub fn to_report<W>(&self, f: W, interp: &mut Artichoke) -> fmt::Result
     where
         W: fmt::Write,
     {
-        let Self { result, output } = self;
-
-        for line in output.stdout().lines() {
-            if let Ok(line) = str::from_utf8(line) {
-                f.write_str(line)?;
-            } else {
-                format_debug_escape_into(&mut f, line)?;
-            }
-            f.write_str("\n")?;
-        }
-
-        if !output.stderr().is_empty() {
-            f.write_str("--- stderr:")?;
-            for line in output.stderr().lines() {
-                if let Ok(line) = str::from_utf8(line) {
-                    f.write_str(line)?;
-                } else {
-                    format_debug_escape_into(&mut f, line)?;
-                }
-                f.write_str("\n")?;
-            }
-        }
+        self.report(interp).write_text(f)
+    }
 
-        match result {
-            Ok(value) => {
-                f.write_str("=> ")?;
-                let value_debug = value.inspect(interp);
-                for line in value_debug.lines() {
-                    if let Ok(line) = str::from_utf8(line) {
-                        f.write_str(line)?;
-                    } else {
-                        format_debug_escape_into(&mut f, line)?;
-                    }
-                    f.write_str("\n")?;
-                }
-            }
-            Err(exc) => write!(f, "{exc}")?,
-        }
+    /// Coalesce stdout, stderr, and `returned_value.inspect` into an output
+    /// report rendered according to `options`.
+    ///
+    /// See [`Report::write_text_with_options`] for more details.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn to_report_with_options<W>(
+        &self,
+        f: W,
+        interp: &mut Artichoke,
+        options: TextOptions,
+    ) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        self.report(interp).write_text_with_options(f, options)
+    }
 
-        Ok(())
+    /// Serialize the report for this eval as JSON.
+    ///
+    /// See [`Report::write_json`] for more details.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn to_json_report<W>(&self, f: W, interp: &mut Artichoke) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        self.report(interp).write_json(f)
     }
 }
 
@@ -92,7 +153,14 @@ where
 ///
 /// This wrapper implements [`Eval`] from artichoke-core.
 #[derive(Debug)]
-pub struct Interp(Option<Artichoke>);
+pub struct Interp {
+    interp: Option<Artichoke>,
+    time_limit: Option<Duration>,
+    memory_limit: Option<usize>,
+    output_callback: Option<output::Callback>,
+    determinism: Option<Determinism>,
+    files: FileSystem,
+}
 
 impl Interp {
     /// Construct a new Artichoke interpreter.
@@ -106,7 +174,48 @@ impl Interp {
     pub fn new() -> Result<Self, Error> {
         let mut interp = artichoke::interpreter()?;
         interp.push_context(unsafe { Context::new_unchecked(crate::REPL_FILENAME) })?;
-        Ok(Self(Some(interp)))
+        Ok(Self {
+            interp: Some(interp),
+            time_limit: None,
+            memory_limit: None,
+            output_callback: None,
+            determinism: None,
+            files: FileSystem::new(),
+        })
+    }
+
+    /// Returns `true` if the underlying interpreter has been closed.
+    ///
+    /// A closed interpreter fails every eval with an error.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.interp.is_none()
+    }
+
+    /// Close the underlying interpreter.
+    ///
+    /// This function is idempotent.
+    pub fn close(&mut self) {
+        if let Some(mut interp) = self.interp.take() {
+            // The quota must be disarmed before the interpreter's memory is
+            // released. It is only left armed if an eval panicked.
+            let _ = quota::disarm(&mut interp);
+            interp.close();
+        }
+    }
+
+    /// Set the wall clock execution budget for subsequent evals.
+    ///
+    /// An eval which runs past its budget is interrupted from inside the VM
+    /// and produces a timeout report of the form `Execution exceeded 5s`
+    /// instead of its result. See [`deadline`] for how the budget is enforced.
+    ///
+    /// The budget also covers inspecting the returned value, which runs Ruby
+    /// code after the eval itself has finished.
+    ///
+    /// Passing [`None`] removes the budget.
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.time_limit = limit;
     }
 
     /// Retrieve information about the current Artichoke build.
@@ -115,28 +224,344 @@ impl Interp {
     ///
     /// [`build_info`]: meta::build_info
     pub fn metadata(&mut self) -> Option<String> {
-        self.0.as_mut().map(meta::build_info)
+        self.interp.as_mut().map(meta::build_info)
+    }
+
+    /// Attach a standard input buffer to this interpreter.
+    ///
+    /// Subsequent evals read from `input` with `gets`, `STDIN.read`,
+    /// `$stdin.each_line`, `ARGF`, and friends. Attaching a new buffer
+    /// replaces any unread input left over from a previous eval.
+    ///
+    /// # Errors
+    ///
+    /// If the interpreter has been closed or the stdin prelude fails to load,
+    /// an error is returned.
+    pub fn set_stdin(&mut self, input: &[u8]) -> Result<(), Error> {
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        stdin::attach(interp, input)
+    }
+
+    /// Eval `code` and return the source with every line ending in a `# =>`
+    /// marker annotated with the inspected value of that line's expression.
+    ///
+    /// The eval is subject to the same limits, determinism, and output
+    /// capture as [`eval_to_structured_report`](Self::eval_to_structured_report).
+    /// Captured output and any exception are appended to the annotated source
+    /// as trailing comments, see [`annotate::write_report`].
+    ///
+    /// See [`annotate`](crate::annotate) for more details.
+    ///
+    /// # Errors
+    ///
+    /// If the interpreter has been closed or the annotations cannot be
+    /// extracted from the interpreter, an error is returned.
+    pub fn annotate(&mut self, code: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut markers = Vec::new();
+        let report = self
+            .report_with(|interp| {
+                let inner = interp
+                    .interp
+                    .as_mut()
+                    .ok_or_else(InterpreterExtractError::new)?;
+                markers = annotate::prepare(inner, code)?;
+                interp.eval(&annotate::instrument(code, &markers))
+            })
+            .ok_or_else(InterpreterExtractError::new)?;
+
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        let values = annotate::values(interp, &markers)?;
+        let mut annotated = annotate::rewrite(code, &markers, &values);
+        annotate::write_report(&mut annotated, &report);
+        Ok(annotated)
+    }
+
+    /// Parse `code` without evaluating it and return any syntax errors and
+    /// warnings.
+    ///
+    /// See [`syntax::check`] for more details.
+    ///
+    /// # Errors
+    ///
+    /// If the interpreter has been closed, an error is returned.
+    pub fn check_syntax(&mut self, code: &[u8]) -> Result<Vec<Diagnostic>, Error> {
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        syntax::check(interp, code)
+    }
+
+    /// Set the argument vector and environment variables for subsequent
+    /// evals.
+    ///
+    /// `ARGV` is replaced with `argv` and `ENV` is replaced with exactly the
+    /// variables in `env`. Variables set by a previous eval are removed.
+    ///
+    /// # Errors
+    ///
+    /// If the interpreter has been closed, the process prelude fails to load,
+    /// or a variable cannot be set, an error is returned.
+    pub fn set_process(&mut self, argv: &[Vec<u8>], env: &Env) -> Result<(), Error> {
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        process::attach(interp, argv, env)
+    }
+
+    /// Set the memory quota for subsequent evals, in bytes.
+    ///
+    /// Growing the interpreter's memory, including the buffers behind strings
+    /// and arrays, by more than the quota over the course of an eval raises
+    /// `NoMemoryError` from inside the VM. See [`quota`] for how allocations
+    /// are counted.
+    ///
+    /// Passing [`None`] removes the quota.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Deliver output to `callback` while subsequent evals are running.
+    ///
+    /// Output is passed to the callback as soon as it is written, in the
+    /// order it was written, and is still included in eval reports. See
+    /// [`output`] for how output is delivered.
+    ///
+    /// Passing [`None`] stops streaming.
+    pub fn set_output_callback(&mut self, callback: Option<output::Callback>) {
+        self.output_callback = callback;
+    }
+
+    /// Make subsequent evals reproducible.
+    ///
+    /// Before each eval, `Random::DEFAULT` is seeded with
+    /// [`seed`](Determinism::seed) and `Time.now` is pinned to
+    /// [`now`](Determinism::now). Reports produced by these evals echo the
+    /// seed and instant so the run can be repeated exactly.
+    ///
+    /// Passing [`None`] restores the real clock and reseeds the random number
+    /// generator from system entropy.
+    pub fn set_determinism(&mut self, determinism: Option<Determinism>) {
+        self.determinism = determinism;
+    }
+
+    /// Mount a virtual filesystem on this interpreter.
+    ///
+    /// Mounted files can be loaded with `require` and `require_relative`, read
+    /// and written with `File`, and run as an entrypoint with
+    /// [`eval_file`](Eval::eval_file).
+    ///
+    /// Files stay defined on the load path for the lifetime of the
+    /// interpreter, even if a later mount omits them. See [`vfs`] for how this
+    /// affects `require` on a long-lived interpreter.
+    ///
+    /// # Errors
+    ///
+    /// If the interpreter has been closed or the files fail to mount, an error
+    /// is returned.
+    pub fn mount(&mut self, files: &FileSystem) -> Result<(), Error> {
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        vfs::mount(interp, files)?;
+        self.files = files.clone();
+        Ok(())
+    }
+
+    /// Retrieve the contents of the mounted virtual filesystem, including any
+    /// files written or deleted by Ruby code.
+    ///
+    /// # Errors
+    ///
+    /// If the interpreter has been closed or the files cannot be extracted
+    /// from the interpreter, an error is returned.
+    pub fn files(&mut self) -> Result<FileSystem, Error> {
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        vfs::snapshot(interp)
     }
 
     /// Construct a string report from the raw output of an interpreter eval.
     ///
     /// See [`Reporter`] for more details.
     pub fn eval_to_report(&mut self, code: &[u8]) -> Option<String> {
-        let result = self.eval(code);
-        if let Some(ref mut interp) = self.0 {
-            let state = interp.state.as_mut()?;
-            let output = mem::replace(&mut state.output, Captured::new());
-
-            let reporter = Reporter { result, output };
-            let mut report = String::new();
-            reporter.to_report(&mut report, interp).ok()?;
-            Some(report)
-        } else {
-            None
+        let report = self.eval_to_structured_report(code)?;
+        let mut out = String::new();
+        report.write_text(&mut out).ok()?;
+        Some(out)
+    }
+
+    /// Construct a JSON report from the raw output of an interpreter eval.
+    ///
+    /// See [`Report::write_json`] for more details.
+    pub fn eval_to_json_report(&mut self, code: &[u8]) -> Option<String> {
+        let report = self.eval_to_structured_report(code)?;
+        let mut out = String::new();
+        report.write_json(&mut out).ok()?;
+        Some(out)
+    }
+
+    /// Construct a structured report from the raw output of an interpreter
+    /// eval.
+    ///
+    /// See [`Reporter`] for more details.
+    pub fn eval_to_structured_report(&mut self, code: &[u8]) -> Option<Report> {
+        self.report_with(|interp| interp.eval(code))
+    }
+
+    /// Construct a structured report from the raw output of running a file
+    /// from the mounted virtual filesystem.
+    ///
+    /// See [`Reporter`] and [`mount`](Self::mount) for more details.
+    pub fn eval_file_to_structured_report(&mut self, file: &Path) -> Option<Report> {
+        self.report_with(|interp| interp.eval_file(file))
+    }
+
+    /// Construct a structured report from the raw output of an eval located
+    /// at `line` of `filename`.
+    ///
+    /// See [`eval_at`](Self::eval_at) and [`Reporter`] for more details.
+    pub fn eval_at_to_structured_report(
+        &mut self,
+        code: &[u8],
+        filename: &[u8],
+        line: u32,
+    ) -> Option<Report> {
+        self.report_with(|interp| interp.eval_at(code, filename, line))
+    }
+
+    /// Eval a byte string as if it were located in `filename` starting at the
+    /// 1-based `line`.
+    ///
+    /// `__FILE__`, `__LINE__`, and backtraces refer to the original location
+    /// of the code, which allows snippets embedded in a larger file to report
+    /// positions in that file. A `line` of `0` is treated as `1`.
+    ///
+    /// # Errors
+    ///
+    /// If `filename` contains a NUL byte or is in the `playground/` directory
+    /// reserved for the playground's preludes, or `line` is greater than
+    /// [`MAX_LINE`], an [`ArgumentError`] is returned.
+    ///
+    /// If an exception occurs when running the provided Ruby code, an error is
+    /// returned.
+    pub fn eval_at(
+        &mut self,
+        code: &[u8],
+        filename: &[u8],
+        line: u32,
+    ) -> Result<value::Value, Error> {
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        let context = Context::new(filename.to_vec())
+            .ok_or_else(|| ArgumentError::from("filename must not contain NUL bytes"))?;
+        if filename.starts_with(prelude::DIR.as_bytes()) {
+            let message = format!("filename must not be in {}", prelude::DIR);
+            return Err(ArgumentError::from(message).into());
         }
+        let Ok(line) = u16::try_from(line.max(1)) else {
+            let message = format!("line must be at most {MAX_LINE}, got {line}");
+            return Err(ArgumentError::from(message).into());
+        };
+
+        interp.push_context(context)?;
+        // The parser context's line counter is shared by every eval on the
+        // interpreter, so it is restored once `code` has been evaluated.
+        let lineno = set_lineno(interp, line)?;
+        let result = interp.eval(code);
+        let restored = set_lineno(interp, lineno);
+        interp.pop_context()?;
+        restored?;
+        result
+    }
+
+    fn report_with<F>(&mut self, eval: F) -> Option<Report>
+    where
+        F: FnOnce(&mut Self) -> Result<value::Value, Error>,
+    {
+        let mut applied = None;
+        if let Some(interp) = self.interp.as_mut() {
+            // Failing to record the transcript only degrades the interleaved
+            // layout to the grouped layout.
+            let _ = transcript::reset(interp);
+            // Only claim the eval is reproducible if the seed and clock were
+            // actually applied.
+            if determinism::apply(interp, self.determinism).is_ok() {
+                applied = self.determinism;
+            }
+        }
+
+        let armed = match self.interp.as_mut() {
+            Some(interp) => self
+                .memory_limit
+                .map_or(Ok(()), |limit| quota::arm(interp, limit))
+                .and_then(|()| {
+                    self.time_limit
+                        .map_or(Ok(()), |limit| deadline::arm(interp, limit))
+                })
+                .and_then(|()| {
+                    self.output_callback
+                        .map_or(Ok(()), |callback| output::arm(interp, callback))
+                }),
+            None => Ok(()),
+        };
+        let start = Instant::now();
+        let result = armed.and_then(|()| eval(self));
+        let duration = start.elapsed();
+
+        let interp = self.interp.as_mut()?;
+        let mut reporter = Reporter {
+            result,
+            output: Captured::new(),
+            duration,
+        };
+        // Describing the returned value runs Ruby code, so it happens while
+        // the limits are still armed and before the output is collected.
+        let outcome = reporter.outcome(interp);
+        let timed_out = matches!(&reporter.result, Err(err) if deadline::is_timeout(err))
+            || deadline::expired();
+
+        // Disarming only fails if the interpreter cannot be accessed, in which
+        // case no more Ruby code can run on it.
+        let _ = deadline::disarm(interp);
+        let _ = quota::disarm(interp);
+        let _ = output::disarm(interp);
+        let state = interp.state.as_mut()?;
+        reporter.output = mem::replace(&mut state.output, Captured::new());
+
+        let mut report = reporter.report_with_outcome(interp, outcome);
+        report.determinism = applied;
+
+        if let Some(limit) = self.time_limit.filter(|_| timed_out) {
+            report.outcome = Outcome::Timeout(limit);
+        }
+        Some(report)
     }
 }
 
+/// Set the line the parser assigns to the first line of the next eval on the
+/// given interpreter and return the previous line.
+fn set_lineno(interp: &mut Artichoke, line: u16) -> Result<u16, Error> {
+    let parser = interp
+        .state
+        .as_mut()
+        .and_then(|state| state.parser.as_mut())
+        .ok_or_else(InterpreterExtractError::new)?;
+    Ok(mem::replace(&mut parser.context_mut().lineno, line))
+}
+
 impl Eval for Interp {
     type Value = value::Value;
     type Error = Error;
@@ -150,7 +575,10 @@ impl Eval for Interp {
     ///
     /// For other possible failures, see [`Artichoke::eval`].
     fn eval(&mut self, code: &[u8]) -> Result<Self::Value, Self::Error> {
-        let interp = self.0.as_mut().ok_or_else(InterpreterExtractError::new)?;
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
         interp.eval(code)
     }
 
@@ -160,18 +588,42 @@ impl Eval for Interp {
         Err(exc.into())
     }
 
-    /// This operation is not supported in the playground.
-    fn eval_file(&mut self, _file: &Path) -> Result<Self::Value, Self::Error> {
-        let exc =
-            NotImplementedError::from("Evaling sources from files is not supported on the web");
-        Err(exc.into())
+    /// Eval a file from the mounted virtual filesystem.
+    ///
+    /// `__FILE__` and backtraces refer to the file's path, and
+    /// `require_relative` resolves relative to it.
+    ///
+    /// # Errors
+    ///
+    /// If the file does not exist in the mounted virtual filesystem, a
+    /// [`LoadError`] is returned.
+    ///
+    /// If an exception occurs when running the file, an error is returned.
+    ///
+    /// See [`mount`](Self::mount).
+    fn eval_file(&mut self, file: &Path) -> Result<Self::Value, Self::Error> {
+        let interp = self
+            .interp
+            .as_mut()
+            .ok_or_else(InterpreterExtractError::new)?;
+        let not_found = || LoadError::from(format!("cannot load such file -- {}", file.display()));
+
+        let path = file
+            .to_str()
+            .and_then(FileSystem::normalize)
+            .ok_or_else(not_found)?;
+        let code = self.files.read(path).ok_or_else(not_found)?.to_vec();
+        let context = Context::new(path.as_bytes().to_vec()).ok_or_else(not_found)?;
+
+        interp.push_context(context)?;
+        let result = interp.eval(&code);
+        interp.pop_context()?;
+        result
     }
 }
 
 impl Drop for Interp {
     fn drop(&mut self) {
-        if let Some(interp) = self.0.take() {
-            interp.close();
-        }
+        self.close();
     }
 }
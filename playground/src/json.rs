@@ -0,0 +1,92 @@
+//! Minimal JSON serialization helpers.
+//!
+//! The playground only emits JSON, it never parses it, so rather than pulling
+//! in a serialization framework these helpers write JSON tokens directly into
+//! any [`fmt::Write`].
+
+use std::fmt::{self, Write as _};
+
+use bstr::ByteSlice;
+
+/// Write `bytes` as a quoted JSON string.
+///
+/// Byte sequences which are not valid UTF-8 are replaced with U+FFFD
+/// REPLACEMENT CHARACTER.
+pub fn write_string<W>(mut f: W, bytes: &[u8]) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    f.write_char('"')?;
+    for ch in bytes.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            ch if ch.is_control() => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Write a JSON array of strings.
+pub fn write_string_array<W, I, T>(mut f: W, items: I) -> fmt::Result
+where
+    W: fmt::Write,
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    f.write_char('[')?;
+    for (idx, item) in items.into_iter().enumerate() {
+        if idx > 0 {
+            f.write_char(',')?;
+        }
+        write_string(&mut f, item.as_ref())?;
+    }
+    f.write_char(']')
+}
+
+/// Write an error object of the form `{"error":"message"}`.
+///
+/// Exports which return JSON use this shape when they fail to produce their
+/// usual payload, so callers can always parse the result.
+pub fn write_error<W>(mut f: W, message: &[u8]) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    f.write_str(r#"{"error":"#)?;
+    write_string(&mut f, message)?;
+    f.write_char('}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_error, write_string, write_string_array};
+
+    #[test]
+    fn escapes_strings() {
+        let mut s = String::new();
+        write_string(&mut s, b"a\"b\\c\nd\x01\xFF").unwrap();
+        assert_eq!(s, "\"a\\\"b\\\\c\\nd\\u0001\u{FFFD}\"");
+    }
+
+    #[test]
+    fn writes_string_arrays() {
+        let mut s = String::new();
+        write_string_array(&mut s, ["a", "b"]).unwrap();
+        assert_eq!(s, r#"["a","b"]"#);
+
+        let mut s = String::new();
+        write_string_array(&mut s, Vec::<Vec<u8>>::new()).unwrap();
+        assert_eq!(s, "[]");
+    }
+
+    #[test]
+    fn writes_error_objects() {
+        let mut s = String::new();
+        write_error(&mut s, b"Fatal \"error\"").unwrap();
+        assert_eq!(s, r#"{"error":"Fatal \"error\""}"#);
+    }
+}
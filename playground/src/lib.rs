@@ -18,12 +18,28 @@
 
 //! The Artichoke Wasm playground.
 
+pub mod annotate;
+pub mod ansi;
+pub mod deadline;
+pub mod determinism;
 #[cfg(target_os = "emscripten")]
 pub mod emscripten;
 pub mod ffi;
+mod hook;
 pub mod interpreter;
+mod json;
 pub mod meta;
+pub mod output;
+mod prelude;
+pub mod process;
+pub mod quota;
+pub mod registry;
+pub mod report;
+mod stdin;
 pub mod string;
+pub mod syntax;
+mod transcript;
+pub mod vfs;
 
 /// Filename for inline code executed on the playground frontend via the embedded
 /// code editor.
@@ -15,6 +15,10 @@
 #![warn(variant_size_differences)]
 
 //! Entrypoint for Artichoke Wasm playground.
+//!
+//! Native builds run a Ruby source file, or stdin, through the playground
+//! interpreter and print the report the playground output pane would show.
+//! The process exits with a non-zero status if the program raises an exception.
 
 #[cfg(target_os = "emscripten")]
 fn main() {
@@ -25,9 +29,69 @@ fn main() {
 
 #[cfg(not(target_os = "emscripten"))]
 fn main() {
-    eprintln!("The playground only supports the wasm32-unknown-emscripten target.");
-    eprintln!(
-        "See the `build` scripts in `package.json` and `scripts/build-wasm.rb` for a build recipe."
-    );
-    std::process::exit(1);
+    use std::env;
+    use std::fs;
+    use std::io::{self, IsTerminal, Read, Write};
+    use std::process;
+
+    use playground::ffi::State;
+
+    const USAGE: &str = "Usage: playground [FILE]";
+
+    let read_stdin = || {
+        let mut input = Vec::new();
+        io::stdin()
+            .read_to_end(&mut input)
+            .map(|_| input)
+            .map_err(|err| {
+                eprintln!("playground: stdin: {err}");
+            })
+    };
+
+    let mut args = env::args_os().skip(1);
+    let path = args.next();
+    if args.next().is_some() {
+        eprintln!("{USAGE}");
+        process::exit(2);
+    }
+
+    // With no file argument, or a file argument of `-`, the program is read
+    // from stdin. Otherwise stdin is passed through to the program, unless it
+    // is a terminal.
+    let input = match path {
+        Some(path) if path != "-" => fs::read(&path)
+            .map_err(|err| {
+                eprintln!("playground: {}: {err}", path.to_string_lossy());
+            })
+            .and_then(|code| {
+                if io::stdin().is_terminal() {
+                    Ok((code, Vec::new()))
+                } else {
+                    read_stdin().map(|stdin| (code, stdin))
+                }
+            }),
+        _ => read_stdin().map(|code| (code, Vec::new())),
+    };
+    let Ok((code, stdin)) = input else {
+        process::exit(1);
+    };
+
+    // Run through the same code path as `artichoke_eval` so the output
+    // matches the playground output pane.
+    let mut state = State::default();
+    state.set_stdin(stdin);
+    let (report, status) = match state.eval_text(&code) {
+        Ok(report) => (report, 0),
+        Err(report) => (report, 1),
+    };
+
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(report.as_bytes());
+    // Exception reports do not end in a newline.
+    if !report.ends_with('\n') {
+        let _ = stdout.write_all(b"\n");
+    }
+    let _ = stdout.flush();
+    state.close();
+    process::exit(status);
 }
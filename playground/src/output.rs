@@ -0,0 +1,213 @@
+//! Interpreter output streams.
+//!
+//! Eval reports record captured stdout and stderr as a sequence of [`Chunk`]s
+//! in the order they were written.
+//!
+//! Output can also be delivered while an eval is still running. [`arm`]
+//! registers a host [`Callback`] and installs the VM's code fetch hook, which
+//! checks Artichoke's capture buffers before each instruction and passes any
+//! bytes written since the last check to the callback. A native method like
+//! `puts` returns to the VM once it has written its output, so the callback
+//! sees each write as soon as it happens and in the order it was made.
+//! [`disarm`] delivers whatever is left once the eval finishes.
+//!
+//! Streamed output is still captured, so eval reports include it as well.
+
+use std::cell::Cell;
+use std::fmt;
+
+use artichoke::backend::ffi::InterpreterExtractError;
+use artichoke::backend::state::State;
+use artichoke::backend::sys;
+use artichoke::prelude::*;
+
+use crate::{hook, json};
+
+/// Host function which receives output while an eval is running.
+///
+/// The callback is passed the [file descriptor](Stream::fd) of the stream
+/// written to and a pointer to and length of the bytes written. The bytes are
+/// only valid for the duration of the call.
+///
+/// The callback runs while the interpreter is in the middle of an eval, so it
+/// must not call back into the playground.
+pub type Callback = extern "C" fn(fd: u32, bytes: *const u8, len: usize);
+
+/// Streaming state for the eval running on this thread.
+#[derive(Debug, Clone, Copy)]
+struct Sink {
+    /// Where output is delivered.
+    callback: Callback,
+    /// Length of the captured stdout already delivered.
+    stdout: usize,
+    /// Length of the captured stderr already delivered.
+    stderr: usize,
+}
+
+thread_local! {
+    /// The sink armed on this thread, if any.
+    static SINK: Cell<Option<Sink>> = const { Cell::new(None) };
+}
+
+/// An output stream of the interpreter.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stream {
+    /// Standard output.
+    Stdout,
+    /// Standard error.
+    Stderr,
+}
+
+impl Stream {
+    /// The name of this stream.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::output::Stream;
+    ///
+    /// assert_eq!(Stream::Stdout.name(), "stdout");
+    /// assert_eq!(Stream::Stderr.name(), "stderr");
+    /// ```
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+
+    /// The file descriptor of this stream.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::output::Stream;
+    ///
+    /// assert_eq!(Stream::Stdout.fd(), 1);
+    /// assert_eq!(Stream::Stderr.fd(), 2);
+    /// ```
+    #[must_use]
+    pub const fn fd(self) -> u32 {
+        match self {
+            Self::Stdout => 1,
+            Self::Stderr => 2,
+        }
+    }
+}
+
+/// A contiguous run of bytes written to a single output stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The stream these bytes were written to.
+    pub stream: Stream,
+    /// The bytes written to the stream.
+    pub bytes: Vec<u8>,
+}
+
+impl Chunk {
+    /// Serialize this chunk as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// { "stream": "stdout", "bytes": "..." }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        write!(f, r#"{{"stream":"{}","bytes":"#, self.stream.name())?;
+        json::write_string(&mut f, &self.bytes)?;
+        f.write_str("}")
+    }
+}
+
+/// Deliver output written by the given interpreter to `callback` while Ruby
+/// code runs.
+///
+/// Only output written after this call is delivered. Streaming applies to
+/// every eval until [`disarm`] is called.
+///
+/// # Errors
+///
+/// If the interpreter cannot be accessed, an error is returned.
+pub fn arm(interp: &mut Artichoke, callback: Callback) -> Result<(), Error> {
+    let state = interp
+        .state
+        .as_ref()
+        .ok_or_else(InterpreterExtractError::new)?;
+    let sink = Sink {
+        callback,
+        stdout: state.output.stdout().len(),
+        stderr: state.output.stderr().len(),
+    };
+    SINK.with(|cell| cell.set(Some(sink)));
+    hook::sync(interp)
+}
+
+/// Deliver any output which has not been delivered yet and stop streaming.
+///
+/// # Errors
+///
+/// If the interpreter cannot be accessed, an error is returned.
+pub fn disarm(interp: &mut Artichoke) -> Result<(), Error> {
+    // SAFETY: No Ruby code is running, so the interpreter state is owned by
+    // the VM for the duration of the closure, as it is while the hook runs.
+    unsafe {
+        interp.with_ffi_boundary(|mrb| flush(mrb))?;
+    }
+    SINK.with(|cell| cell.set(None));
+    hook::sync(interp)
+}
+
+/// Returns `true` if output is being streamed on this thread.
+pub(crate) fn is_armed() -> bool {
+    SINK.with(Cell::get).is_some()
+}
+
+/// Pass output captured since the previous flush to the armed callback.
+///
+/// # Safety
+///
+/// `mrb` must be a live interpreter whose user data is its Artichoke state,
+/// which is the case whenever the VM is running.
+pub(crate) unsafe fn flush(mrb: *mut sys::mrb_state) {
+    let Some(mut sink) = SINK.with(Cell::get) else {
+        return;
+    };
+    let Some(state) = (unsafe { (*mrb).ud.cast::<State>().as_ref() }) else {
+        return;
+    };
+    let streams = [
+        (Stream::Stdout, state.output.stdout(), &mut sink.stdout),
+        (Stream::Stderr, state.output.stderr(), &mut sink.stderr),
+    ];
+    for (stream, captured, delivered) in streams {
+        if let Some(bytes) = captured.get(*delivered..).filter(|bytes| !bytes.is_empty()) {
+            (sink.callback)(stream.fd(), bytes.as_ptr(), bytes.len());
+            *delivered = captured.len();
+        }
+    }
+    SINK.with(|cell| cell.set(Some(sink)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunk, Stream};
+
+    #[test]
+    fn chunk_json() {
+        let chunk = Chunk {
+            stream: Stream::Stderr,
+            bytes: b"oops\n".to_vec(),
+        };
+        let mut s = String::new();
+        chunk.write_json(&mut s).unwrap();
+        assert_eq!(s, r#"{"stream":"stderr","bytes":"oops\n"}"#);
+    }
+}
@@ -0,0 +1,44 @@
+//! Ruby preludes which implement playground features on top of the
+//! interpreter.
+//!
+//! Several features, like `STDIN` and the virtual filesystem, are simpler to
+//! write in Ruby than against the interpreter's native API. Each ships its
+//! Ruby source as a [`Prelude`] which is loaded on demand, the first time the
+//! feature is used on an interpreter.
+
+use artichoke::prelude::*;
+
+/// Directory in the interpreter's virtual filesystem which holds the preludes.
+///
+/// User files may not be placed in this directory, so they can neither shadow
+/// a prelude nor be mistaken for one in backtraces.
+pub const DIR: &str = "playground/";
+
+/// Ruby source which is loaded into an interpreter on demand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prelude {
+    /// Path in the interpreter's virtual filesystem the source is loaded from.
+    pub path: &'static str,
+    /// Ruby source for the prelude.
+    pub source: &'static [u8],
+}
+
+impl Prelude {
+    /// Require this prelude on the given interpreter, defining it in the
+    /// virtual filesystem first if needed.
+    ///
+    /// Requiring is idempotent, so this is cheap to call before every use of
+    /// the feature the prelude implements.
+    ///
+    /// # Errors
+    ///
+    /// If the prelude cannot be defined or raises when it is required, an
+    /// error is returned.
+    pub fn load(&self, interp: &mut Artichoke) -> Result<(), Error> {
+        if !interp.source_is_file(self.path)? {
+            interp.def_rb_source_file(self.path, self.source)?;
+        }
+        interp.require_source(self.path)?;
+        Ok(())
+    }
+}
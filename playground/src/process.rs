@@ -0,0 +1,79 @@
+//! Per-eval `ARGV` and `ENV` for playground evals.
+//!
+//! The playground has no command line or host environment. Instead, the
+//! frontend supplies an argument vector and a set of environment variables
+//! which are installed before each eval.
+//!
+//! `ENV` is sandboxed: every eval starts from exactly the supplied variables,
+//! so variables set by one run never leak into the next, even on a long-lived
+//! session interpreter.
+
+use std::collections::BTreeMap;
+
+use artichoke::prelude::*;
+
+use crate::prelude::Prelude;
+
+/// Ruby source for the process prelude.
+const PRELUDE: Prelude = Prelude {
+    path: "playground/process.rb",
+    source: br#"
+ARGV = [] unless Object.const_defined?(:ARGV)
+
+module Playground
+  def self.reset_env
+    ENV.to_h.each_key { |name| ENV[name] = nil }
+    ENV
+  end
+end
+"#,
+};
+
+/// Environment variables keyed by name.
+pub type Env = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Returns `true` if `name` may be used as the name of an environment
+/// variable.
+///
+/// Names must be non-empty and must not contain `=` or NUL bytes.
+///
+/// # Examples
+///
+/// ```
+/// use playground::process::is_valid_env_name;
+///
+/// assert!(is_valid_env_name(b"HOME"));
+/// assert!(!is_valid_env_name(b""));
+/// assert!(!is_valid_env_name(b"A=B"));
+/// assert!(!is_valid_env_name(b"A\0"));
+/// ```
+#[must_use]
+pub fn is_valid_env_name(name: &[u8]) -> bool {
+    !name.is_empty() && !name.contains(&b'=') && !name.contains(&b'\0')
+}
+
+/// Install the process prelude on the given interpreter, replace the contents
+/// of `ARGV` with `argv`, and replace the contents of `ENV` with `env`.
+///
+/// # Errors
+///
+/// If the prelude fails to load or the arguments or variables cannot be set,
+/// an error is returned.
+pub fn attach(interp: &mut Artichoke, argv: &[Vec<u8>], env: &Env) -> Result<(), Error> {
+    PRELUDE.load(interp)?;
+
+    let args = interp.eval(b"ARGV")?;
+    args.funcall(interp, "clear", &[], None)?;
+    for arg in argv {
+        let arg = interp.convert_mut(arg.as_slice());
+        args.funcall(interp, "push", &[arg], None)?;
+    }
+
+    let vars = interp.eval(b"Playground.reset_env")?;
+    for (name, value) in env {
+        let name = interp.convert_mut(name.as_slice());
+        let value = interp.convert_mut(value.as_slice());
+        vars.funcall(interp, "[]=", &[name, value], None)?;
+    }
+    Ok(())
+}
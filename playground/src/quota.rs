@@ -0,0 +1,331 @@
+//! Per-eval memory quotas enforced at allocation time.
+//!
+//! An interpreter allocates memory in two places. mruby requests memory for
+//! objects and VM state through the allocation function stored on its
+//! `mrb_state`, and Artichoke keeps the contents of strings and arrays in
+//! buffers from the Rust global allocator. [`arm`] meters both: it swaps the
+//! interpreter's allocation function for one which forwards to the original,
+//! and this crate's [`Metered`] global allocator charges Rust allocations to
+//! the armed quota. Together they keep a running total of how many bytes the
+//! interpreter has allocated since the quota was armed.
+//!
+//! mruby requests which would grow the total past the quota are refused before
+//! they reach the C allocator. mruby handles a refused request like any other
+//! out of memory condition: it runs a full garbage collection, retries, and if
+//! the request still does not fit, raises `NoMemoryError` from the allocation
+//! site.
+//!
+//! Rust allocations are never refused, because Rust aborts the process when an
+//! allocation fails. Instead, the VM's code fetch hook checks the total before
+//! each instruction. Once it is over the quota, the hook runs a full garbage
+//! collection and raises `NoMemoryError` if that does not free enough memory.
+//! Either way Ruby code sees an ordinary exception and the interpreter remains
+//! usable.
+//!
+//! Block sizes are read back from the C allocator, so freeing memory which
+//! was allocated before the quota was armed never drives the total below
+//! zero. Only one interpreter per thread may have a quota armed at a time.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::ptr;
+
+use artichoke::backend::sys;
+use artichoke::prelude::*;
+
+use crate::hook;
+
+extern "C" {
+    /// Returns the number of usable bytes in a block returned by the C
+    /// allocator.
+    #[cfg_attr(target_os = "macos", link_name = "malloc_size")]
+    fn malloc_usable_size(ptr: *mut c_void) -> usize;
+}
+
+/// Allocation accounting for an interpreter with an armed quota.
+#[derive(Debug, Clone, Copy)]
+struct Meter {
+    /// The interpreter whose allocations are counted.
+    mrb: *mut sys::mrb_state,
+    /// The interpreter's allocation function before the quota was armed.
+    allocf: sys::mrb_allocf,
+    /// Maximum number of bytes the interpreter may allocate.
+    limit: usize,
+    /// Bytes allocated since the quota was armed.
+    used: usize,
+}
+
+thread_local! {
+    /// Accounting for the interpreter on this thread with an armed quota.
+    ///
+    /// The global allocator reads this on every allocation, so it is a plain
+    /// [`Cell`] which never allocates or panics when accessed.
+    static METER: Cell<Option<Meter>> = const { Cell::new(None) };
+}
+
+/// Global allocator which charges allocations made on a thread with an armed
+/// quota to that quota.
+///
+/// Allocations are forwarded to the [`System`] allocator unchanged.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Metered;
+
+#[global_allocator]
+static ALLOCATOR: Metered = Metered;
+
+// SAFETY: Every method forwards to the system allocator with the same
+// arguments and only adjusts a thread local counter.
+unsafe impl GlobalAlloc for Metered {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            charge(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            charge(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        charge(0, layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new.is_null() {
+            charge(new_size, layout.size());
+        }
+        new
+    }
+}
+
+/// Replace `freed` bytes with `allocated` bytes in the armed quota's total.
+fn charge(allocated: usize, freed: usize) {
+    // The thread local is inaccessible while the thread is being torn down,
+    // which is never while a quota is armed.
+    let _ = METER.try_with(|meter| {
+        if let Some(mut armed) = meter.get() {
+            armed.used = armed.used.saturating_sub(freed).saturating_add(allocated);
+            meter.set(Some(armed));
+        }
+    });
+}
+
+/// Limit the memory allocated by the given interpreter to `limit` bytes.
+///
+/// The quota applies until [`disarm`] is called, which must happen before the
+/// interpreter is closed. Arming an interpreter which already has a quota
+/// replaces the limit and keeps the running total.
+///
+/// # Errors
+///
+/// If another interpreter on this thread has an armed quota, an error is
+/// returned.
+///
+/// If the interpreter cannot be accessed, an error is returned.
+pub fn arm(interp: &mut Artichoke, limit: usize) -> Result<(), Error> {
+    // SAFETY: The allocation function is swapped while no Ruby code is
+    // running, and the metered function forwards to the original one, so
+    // blocks allocated by either can be freed by the other.
+    let armed = unsafe {
+        interp.with_ffi_boundary(|mrb| match METER.with(Cell::get) {
+            Some(meter) if meter.mrb != mrb => false,
+            Some(meter) => {
+                METER.with(|cell| cell.set(Some(Meter { limit, ..meter })));
+                true
+            }
+            None => {
+                METER.with(|cell| {
+                    cell.set(Some(Meter {
+                        mrb,
+                        allocf: (*mrb).allocf,
+                        limit,
+                        used: 0,
+                    }));
+                });
+                (*mrb).allocf = Some(metered_allocf);
+                true
+            }
+        })?
+    };
+    if !armed {
+        return Err(
+            RuntimeError::from("another interpreter on this thread has a memory quota").into(),
+        );
+    }
+    hook::sync(interp)
+}
+
+/// Remove the quota installed by [`arm`] and restore the interpreter's
+/// original allocation function.
+///
+/// Returns the number of bytes allocated while the quota was armed, or
+/// [`None`] if the interpreter had no quota.
+///
+/// # Errors
+///
+/// If the interpreter cannot be accessed, an error is returned.
+pub fn disarm(interp: &mut Artichoke) -> Result<Option<usize>, Error> {
+    // SAFETY: See `arm`.
+    let used = unsafe {
+        interp.with_ffi_boundary(|mrb| {
+            let meter = METER.with(Cell::get).filter(|meter| meter.mrb == mrb)?;
+            METER.with(|cell| cell.set(None));
+            (*mrb).allocf = meter.allocf;
+            Some(meter.used)
+        })?
+    };
+    hook::sync(interp)?;
+    Ok(used)
+}
+
+/// Returns `true` if an interpreter on this thread has an armed quota.
+pub(crate) fn is_armed() -> bool {
+    METER.with(Cell::get).is_some()
+}
+
+/// Returns `true` if the given interpreter uses more memory than its quota
+/// allows, even after a full garbage collection.
+///
+/// # Safety
+///
+/// `mrb` must be a live interpreter which is not in the middle of a garbage
+/// collection.
+pub(crate) unsafe fn exceeded(mrb: *mut sys::mrb_state) -> bool {
+    let over = || {
+        METER
+            .with(Cell::get)
+            .map_or(false, |meter| meter.mrb == mrb && meter.used > meter.limit)
+    };
+    if !over() {
+        return false;
+    }
+    // Garbage which holds Rust buffers is only freed by a collection, which
+    // the Rust allocations themselves never trigger.
+    unsafe {
+        sys::mrb_full_gc(mrb);
+    }
+    over()
+}
+
+unsafe extern "C" fn metered_allocf(
+    mrb: *mut sys::mrb_state,
+    ptr: *mut c_void,
+    size: usize,
+    ud: *mut c_void,
+) -> *mut c_void {
+    // The metered function is only installed while its meter exists.
+    let mut meter = match METER.with(Cell::get) {
+        Some(meter) if meter.mrb == mrb => meter,
+        _ => return ptr::null_mut(),
+    };
+    let allocf = match meter.allocf {
+        Some(allocf) => allocf,
+        None => return ptr::null_mut(),
+    };
+
+    // SAFETY: mruby only passes blocks returned by this allocation function
+    // or the original one, both of which use the C allocator.
+    let old = if ptr.is_null() {
+        0
+    } else {
+        unsafe { malloc_usable_size(ptr) }
+    };
+    if size > old && meter.used.saturating_add(size - old) > meter.limit {
+        // mruby leaves the original block untouched when a reallocation
+        // fails.
+        return ptr::null_mut();
+    }
+
+    let new = unsafe { allocf(mrb, ptr, size, ud) };
+    // The C allocator does not go through the global allocator, so the total
+    // read above is still current.
+    if size == 0 {
+        meter.used = meter.used.saturating_sub(old);
+    } else if !new.is_null() {
+        let new_size = unsafe { malloc_usable_size(new) };
+        meter.used = meter.used.saturating_sub(old).saturating_add(new_size);
+    }
+    METER.with(|cell| cell.set(Some(meter)));
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use artichoke::prelude::*;
+
+    use super::{arm, disarm};
+
+    #[test]
+    fn allocations_past_the_quota_raise_no_memory_error() {
+        let mut interp = artichoke::interpreter().unwrap();
+        arm(&mut interp, 64 * 1024).unwrap();
+        let err = interp.eval(b"'x' * 1_000_000").unwrap_err();
+        assert_eq!(err.name(), "NoMemoryError");
+
+        // The interpreter survives and small allocations still succeed.
+        let value = interp.eval(b"'x' * 10").unwrap();
+        assert_eq!(value.inspect(&mut interp), br#""xxxxxxxxxx""#);
+
+        assert!(disarm(&mut interp).unwrap().is_some());
+        interp.eval(b"'x' * 1_000_000").unwrap();
+        interp.close();
+    }
+
+    #[test]
+    fn string_buffers_count_against_the_quota() {
+        let mut interp = artichoke::interpreter().unwrap();
+        arm(&mut interp, 16 * 1024 * 1024).unwrap();
+        let err = interp
+            .eval(b"(1..).map { 'x' * 1_000_000 }.first(10_000)")
+            .unwrap_err();
+        assert_eq!(err.name(), "NoMemoryError");
+
+        let err = interp.eval(b"Array.new(10_000_000, 0)").unwrap_err();
+        assert_eq!(err.name(), "NoMemoryError");
+
+        disarm(&mut interp).unwrap();
+        interp.close();
+    }
+
+    #[test]
+    fn only_one_interpreter_per_thread_has_a_quota() {
+        let mut first = artichoke::interpreter().unwrap();
+        let mut second = artichoke::interpreter().unwrap();
+        arm(&mut first, 1024 * 1024).unwrap();
+        arm(&mut first, 2 * 1024 * 1024).unwrap();
+        assert!(arm(&mut second, 1024 * 1024).is_err());
+
+        assert!(disarm(&mut first).unwrap().is_some());
+        assert_eq!(disarm(&mut second).unwrap(), None);
+        arm(&mut second, 1024 * 1024).unwrap();
+        disarm(&mut second).unwrap();
+        first.close();
+        second.close();
+    }
+
+    #[test]
+    fn freed_memory_is_credited_back() {
+        let mut interp = artichoke::interpreter().unwrap();
+        arm(&mut interp, 1024 * 1024).unwrap();
+        let value = interp.eval(b"100.times { 'x' * 100_000 }; :done").unwrap();
+        assert_eq!(value.inspect(&mut interp), b":done");
+        assert!(disarm(&mut interp).unwrap().unwrap() <= 1024 * 1024);
+        interp.close();
+    }
+
+    #[test]
+    fn disarm_without_quota_is_none() {
+        let mut interp = artichoke::interpreter().unwrap();
+        assert_eq!(disarm(&mut interp).unwrap(), None);
+        interp.close();
+    }
+}
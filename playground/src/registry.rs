@@ -0,0 +1,276 @@
+//! Generation-checked handle tables.
+//!
+//! Handles are opaque `u32` values which are safe to pass to foreign code.
+//! Unlike raw pointers, a handle which is stale (its value has been removed) or
+//! forged (it was never returned from [`Registry::insert`]) is detected and
+//! rejected on lookup.
+
+/// Number of bits of a handle used to store the slot index.
+const INDEX_BITS: u32 = 16;
+
+/// Mask for extracting the slot index from a handle.
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+
+/// A table of values keyed by opaque, generation-checked handles.
+///
+/// A handle encodes the index of the slot a value is stored in and the
+/// generation of that slot. Each time a value is removed from a slot, the
+/// slot's generation is incremented so handles to the removed value no longer
+/// resolve, even once the slot is reused for a new value.
+///
+/// No valid handle is equal to `0` or [`u32::MAX`], which allows foreign code
+/// to use these values as sentinels.
+#[derive(Debug, Clone)]
+pub struct Registry<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u16>,
+    retired: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Slot<T> {
+    generation: u16,
+    value: Option<T>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Registry<T> {
+    /// Construct a new, empty registry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::registry::Registry;
+    ///
+    /// let registry = Registry::<()>::new();
+    /// assert!(registry.is_empty());
+    /// ```
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            retired: 0,
+        }
+    }
+
+    /// Returns the number of values in the registry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::registry::Registry;
+    ///
+    /// let mut registry = Registry::new();
+    /// assert_eq!(registry.len(), 0);
+    /// registry.insert("Wasm");
+    /// assert_eq!(registry.len(), 1);
+    /// ```
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len() - self.retired
+    }
+
+    /// Returns `true` if the registry contains no values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::registry::Registry;
+    ///
+    /// let mut registry = Registry::new();
+    /// assert!(registry.is_empty());
+    /// registry.insert("Wasm");
+    /// assert!(!registry.is_empty());
+    /// ```
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Store a value in the registry and return a handle to it.
+    ///
+    /// Returns [`None`] if the registry is full.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::registry::Registry;
+    ///
+    /// let mut registry = Registry::new();
+    /// let handle = registry.insert("Wasm").unwrap();
+    /// assert_eq!(registry.get(handle), Some(&"Wasm"));
+    /// ```
+    pub fn insert(&mut self, value: T) -> Option<u32> {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[usize::from(index)];
+            slot.value = Some(value);
+            return Some(encode(index, slot.generation));
+        }
+        // Index `u16::MAX` is never used so no handle is equal to `u32::MAX`.
+        let index = u16::try_from(self.slots.len())
+            .ok()
+            .filter(|&index| index < u16::MAX)?;
+        self.slots.push(Slot {
+            generation: 1,
+            value: Some(value),
+        });
+        Some(encode(index, 1))
+    }
+
+    /// Retrieve a reference to the value identified by `handle`.
+    ///
+    /// Returns [`None`] if the handle is stale or was never returned from
+    /// [`insert`](Self::insert).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::registry::Registry;
+    ///
+    /// let mut registry = Registry::new();
+    /// let handle = registry.insert("Wasm").unwrap();
+    /// assert_eq!(registry.get(handle), Some(&"Wasm"));
+    /// assert_eq!(registry.get(0), None);
+    /// assert_eq!(registry.get(u32::MAX), None);
+    /// ```
+    #[must_use]
+    pub fn get(&self, handle: u32) -> Option<&T> {
+        let (index, generation) = decode(handle);
+        let slot = self.slots.get(usize::from(index))?;
+        if slot.generation != generation {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    /// Retrieve a mutable reference to the value identified by `handle`.
+    ///
+    /// Returns [`None`] if the handle is stale or was never returned from
+    /// [`insert`](Self::insert).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::registry::Registry;
+    ///
+    /// let mut registry = Registry::new();
+    /// let handle = registry.insert(String::from("Wasm")).unwrap();
+    /// registry.get_mut(handle).unwrap().push('!');
+    /// assert_eq!(registry.get(handle).map(String::as_str), Some("Wasm!"));
+    /// ```
+    #[must_use]
+    pub fn get_mut(&mut self, handle: u32) -> Option<&mut T> {
+        let (index, generation) = decode(handle);
+        let slot = self.slots.get_mut(usize::from(index))?;
+        if slot.generation != generation {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+
+    /// Remove the value identified by `handle` from the registry and return
+    /// it.
+    ///
+    /// After a value is removed, its handle is stale and will no longer
+    /// resolve. Returns [`None`] if the handle is stale or was never returned
+    /// from [`insert`](Self::insert).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::registry::Registry;
+    ///
+    /// let mut registry = Registry::new();
+    /// let handle = registry.insert("Wasm").unwrap();
+    /// assert_eq!(registry.remove(handle), Some("Wasm"));
+    /// assert_eq!(registry.remove(handle), None);
+    /// assert_eq!(registry.get(handle), None);
+    /// ```
+    pub fn remove(&mut self, handle: u32) -> Option<T> {
+        let (index, generation) = decode(handle);
+        let slot = self.slots.get_mut(usize::from(index))?;
+        if slot.generation != generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        // Slots whose generation is exhausted are retired rather than reused
+        // so a handle is never aliased.
+        slot.generation = slot.generation.saturating_add(1);
+        if slot.generation < u16::MAX {
+            self.free.push(index);
+        } else {
+            self.retired += 1;
+        }
+        Some(value)
+    }
+}
+
+fn encode(index: u16, generation: u16) -> u32 {
+    (u32::from(generation) << INDEX_BITS) | u32::from(index)
+}
+
+fn decode(handle: u32) -> (u16, u16) {
+    let index = (handle & INDEX_MASK) as u16;
+    let generation = (handle >> INDEX_BITS) as u16;
+    (index, generation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registry;
+
+    #[test]
+    fn handles_are_never_zero_or_max() {
+        let mut registry = Registry::new();
+        for _ in 0..1024 {
+            let handle = registry.insert(()).unwrap();
+            assert_ne!(handle, 0);
+            assert_ne!(handle, u32::MAX);
+        }
+    }
+
+    #[test]
+    fn reused_slots_reject_stale_handles() {
+        let mut registry = Registry::new();
+        let first = registry.insert("first").unwrap();
+        assert_eq!(registry.remove(first), Some("first"));
+
+        let second = registry.insert("second").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(registry.get(first), None);
+        assert_eq!(registry.get(second), Some(&"second"));
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn forged_handles_are_rejected() {
+        let mut registry = Registry::new();
+        let handle = registry.insert("Wasm").unwrap();
+        assert_eq!(registry.get(handle + 1), None);
+        assert_eq!(registry.get(handle ^ (1 << 16)), None);
+        assert_eq!(registry.get_mut(u32::MAX), None);
+        assert_eq!(registry.remove(0), None);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn exhausted_generations_retire_slot() {
+        let mut registry = Registry::new();
+        let mut handle = registry.insert(()).unwrap();
+        for _ in 1..u16::MAX - 1 {
+            registry.remove(handle).unwrap();
+            handle = registry.insert(()).unwrap();
+        }
+        registry.remove(handle).unwrap();
+        assert!(registry.is_empty());
+
+        let handle = registry.insert(()).unwrap();
+        assert_eq!(handle & 0xFFFF, 1);
+    }
+}
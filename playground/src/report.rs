@@ -0,0 +1,817 @@
+//! Structured reports of playground interpreter evals.
+//!
+//! A [`Report`] is the data model behind both the flat text report shown in
+//! the playground output pane and the JSON report consumed by frontends which
+//! want to style stdout, stderr, and the returned value separately.
+
+use std::fmt;
+use std::str;
+use std::time::Duration;
+
+use bstr::ByteSlice;
+use scolapasta_string_escape::format_debug_escape_into;
+
+use crate::ansi;
+use crate::determinism::Determinism;
+use crate::json;
+use crate::output::{Chunk, Stream};
+use crate::prelude;
+use crate::transcript;
+
+/// The outcome of evaluating a Ruby source.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The captured stdout of the interpreter.
+    pub stdout: Vec<u8>,
+    /// The captured stderr of the interpreter.
+    pub stderr: Vec<u8>,
+    /// The captured stdout and stderr in the order they were written.
+    ///
+    /// If empty, the transcript is assumed to be all of
+    /// [`stdout`](Self::stdout) followed by all of [`stderr`](Self::stderr).
+    pub transcript: Vec<Chunk>,
+    /// The value returned from the eval or the exception it raised.
+    pub outcome: Outcome,
+    /// Wall clock time spent evaluating the source.
+    pub duration: Duration,
+    /// The random seed and frozen clock the eval ran with, or [`None`] if the
+    /// eval was not deterministic.
+    pub determinism: Option<Determinism>,
+}
+
+/// How stdout and stderr are arranged in a text report.
+#[derive(Default, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Layout {
+    /// All of stdout followed by all of stderr under a `--- stderr:` header.
+    #[default]
+    Grouped,
+    /// Stdout and stderr in the order they were written, with a header each
+    /// time the output switches streams.
+    Interleaved,
+}
+
+/// How bytes which are not valid UTF-8 are rendered in a text report.
+#[derive(Default, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum BinaryFormat {
+    /// Render invalid bytes as Ruby-style debug escapes, e.g. `\xFF`.
+    #[default]
+    Escape,
+    /// Replace invalid bytes with U+FFFD REPLACEMENT CHARACTER.
+    Replace,
+    /// Render stdout and stderr which contain invalid UTF-8 as a hex dump with
+    /// offsets, in the style of `hexdump -C`.
+    ///
+    /// Other parts of the report, like the returned value and exception
+    /// message, are rendered with debug escapes.
+    HexDump,
+}
+
+/// Options which control how a text report is rendered.
+#[derive(Default, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Arrangement of stdout and stderr.
+    pub layout: Layout,
+    /// Rendering of bytes which are not valid UTF-8.
+    pub binary: BinaryFormat,
+}
+
+/// The value returned by an eval or the exception it raised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The eval completed and returned a value.
+    Value(ValueReport),
+    /// The eval raised an exception.
+    Exception(ExceptionReport),
+    /// The eval ran past its execution budget.
+    Timeout(Duration),
+}
+
+impl Default for Outcome {
+    fn default() -> Self {
+        Self::Value(ValueReport::default())
+    }
+}
+
+/// Information about the value returned by an eval.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ValueReport {
+    /// The output of calling `inspect` on the returned value.
+    pub inspect: Vec<u8>,
+    /// The name of the returned value's class.
+    pub class: Vec<u8>,
+}
+
+/// Information about the exception raised by an eval.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ExceptionReport {
+    /// The name of the exception's class.
+    pub class: String,
+    /// The exception message.
+    pub message: Vec<u8>,
+    /// The VM backtrace of the exception, if any.
+    ///
+    /// See [`frames`](Self::frames) for the backtrace parsed into frames.
+    pub backtrace: Vec<Vec<u8>>,
+}
+
+impl ExceptionReport {
+    /// Construct a new exception report.
+    #[must_use]
+    pub fn new(class: String, message: Vec<u8>, backtrace: Vec<Vec<u8>>) -> Self {
+        Self {
+            class,
+            message,
+            backtrace,
+        }
+    }
+
+    /// Parse the exception's [`backtrace`](Self::backtrace) into [`Frame`]s.
+    #[must_use]
+    pub fn frames(&self) -> Vec<Frame> {
+        self.backtrace
+            .iter()
+            .map(|line| Frame::parse(line))
+            .collect()
+    }
+}
+
+/// A single frame of an exception backtrace.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The file the frame's code lives in.
+    pub file: Vec<u8>,
+    /// The line in [`file`](Self::file) the frame was executing, if known.
+    pub line: Option<u32>,
+    /// The name of the method the frame was executing, if any.
+    pub method: Option<Vec<u8>>,
+    /// Whether the frame is in code written by the playground user.
+    ///
+    /// This includes the code entered in the playground editor, which has a
+    /// [`file`](Self::file) of [`REPL_FILENAME`], code evaluated at a caller
+    /// provided location, and files in the virtual filesystem. It is the
+    /// opposite of [`is_internal`](Self::is_internal).
+    ///
+    /// [`REPL_FILENAME`]: crate::REPL_FILENAME
+    pub is_playground: bool,
+    /// Whether the frame is in code provided by the interpreter or the
+    /// playground runtime rather than user code.
+    ///
+    /// Frontends may collapse runs of internal frames.
+    pub is_internal: bool,
+}
+
+impl Frame {
+    /// Parse a line of a VM backtrace.
+    ///
+    /// Backtrace lines have the form `file:line:in method`, where the line
+    /// number and method are optional.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::report::Frame;
+    ///
+    /// let frame = Frame::parse(b"(playground):3:in fib");
+    /// assert_eq!(frame.file, b"(playground)");
+    /// assert_eq!(frame.line, Some(3));
+    /// assert_eq!(frame.method.as_deref(), Some(&b"fib"[..]));
+    /// assert!(frame.is_playground);
+    /// assert!(!frame.is_internal);
+    /// ```
+    #[must_use]
+    pub fn parse(entry: &[u8]) -> Self {
+        let (location, method) = match entry.find(b":in ") {
+            Some(idx) => {
+                let method = entry[idx + 4..].trim_with(|c| c == '`' || c == '\'');
+                (&entry[..idx], Some(method.to_vec()))
+            }
+            None => (entry, None),
+        };
+        let (file, line) = location
+            .rfind_byte(b':')
+            .and_then(|idx| {
+                let line = str::from_utf8(&location[idx + 1..]).ok()?.parse().ok()?;
+                Some((&location[..idx], Some(line)))
+            })
+            .unwrap_or((location, None));
+
+        // Artichoke's embedded sources live under `/artichoke` and the
+        // playground's Ruby preludes live under `playground/`.
+        let is_internal = file != crate::REPL_FILENAME
+            && (file.is_empty()
+                || line.is_none()
+                || file.starts_with(b"/artichoke/")
+                || file.starts_with(prelude::DIR.as_bytes()));
+        let is_playground = !is_internal;
+
+        Self {
+            file: file.to_vec(),
+            line,
+            method,
+            is_playground,
+            is_internal,
+        }
+    }
+
+    /// Serialize this frame as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// {
+    ///   "file": "(playground)",
+    ///   "line": 3,
+    ///   "method": "fib",
+    ///   "playground": true,
+    ///   "internal": false
+    /// }
+    /// ```
+    ///
+    /// `line` and `method` are `null` if they are not known.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        f.write_str(r#"{"file":"#)?;
+        json::write_string(&mut f, &self.file)?;
+        if let Some(line) = self.line {
+            write!(f, r#","line":{line}"#)?;
+        } else {
+            f.write_str(r#","line":null"#)?;
+        }
+        f.write_str(r#","method":"#)?;
+        if let Some(ref method) = self.method {
+            json::write_string(&mut f, method)?;
+        } else {
+            f.write_str("null")?;
+        }
+        write!(
+            f,
+            r#","playground":{},"internal":{}}}"#,
+            self.is_playground, self.is_internal
+        )
+    }
+}
+
+impl Report {
+    /// Coalesce stdout, stderr, and `returned_value.inspect` into an output
+    /// report suitable for displaying in the playground webapp.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_text<W>(&self, f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        self.write_text_with_layout(f, Layout::Grouped)
+    }
+
+    /// Coalesce stdout, stderr, and `returned_value.inspect` into an output
+    /// report with stdout and stderr arranged according to `layout`.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_text_with_layout<W>(&self, f: W, layout: Layout) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        let options = TextOptions {
+            layout,
+            ..TextOptions::default()
+        };
+        self.write_text_with_options(f, options)
+    }
+
+    /// Coalesce stdout, stderr, and `returned_value.inspect` into an output
+    /// report rendered according to `options`.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_text_with_options<W>(&self, mut f: W, options: TextOptions) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        let binary = options.binary;
+        match options.layout {
+            Layout::Grouped => {
+                write_output(&mut f, &self.stdout, binary)?;
+
+                if !self.stderr.is_empty() {
+                    f.write_str("--- stderr:")?;
+                    write_output(&mut f, &self.stderr, binary)?;
+                }
+            }
+            Layout::Interleaved => {
+                let mut current = Stream::Stdout;
+                for chunk in &self.transcript() {
+                    if chunk.stream != current {
+                        write!(f, "--- {}:", chunk.stream.name())?;
+                        current = chunk.stream;
+                    }
+                    write_output(&mut f, &chunk.bytes, binary)?;
+                }
+            }
+        }
+
+        match self.outcome {
+            Outcome::Value(ref value) => {
+                f.write_str("=> ")?;
+                for line in value.inspect.lines() {
+                    write_line(&mut f, line, binary)?;
+                }
+            }
+            Outcome::Exception(ref exc) => {
+                write!(f, "{} (", exc.class)?;
+                write_bytes(&mut f, &exc.message, binary)?;
+                f.write_str(")")?;
+                let lines = exc.backtrace.iter();
+                for line in lines.filter(|line| !Frame::parse(line).is_internal) {
+                    f.write_str("\n\tfrom ")?;
+                    write_bytes(&mut f, line, binary)?;
+                }
+            }
+            Outcome::Timeout(limit) => {
+                f.write_str("Execution exceeded ")?;
+                write_duration(&mut f, limit)?;
+                f.write_str("\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The captured stdout and stderr in the order they were written.
+    ///
+    /// See [`transcript`](Self::transcript).
+    #[must_use]
+    pub fn transcript(&self) -> Vec<Chunk> {
+        if self.transcript.is_empty() {
+            transcript::grouped(&self.stdout, &self.stderr)
+        } else {
+            self.transcript.clone()
+        }
+    }
+
+    /// Serialize this report as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// {
+    ///   "stdout": "...",
+    ///   "stderr": "...",
+    ///   "transcript": [{ "stream": "stdout", "bytes": "..." }],
+    ///   "spans": { "stdout": [{ "text": "...", ... }], "stderr": [] },
+    ///   "value": { "inspect": "...", "class": "..." },
+    ///   "exception": {
+    ///     "class": "...",
+    ///     "message": "...",
+    ///     "backtrace": ["..."],
+    ///     "frames": [{ "file": "...", "line": 1, "method": null, ... }]
+    ///   },
+    ///   "timeout_us": null,
+    ///   "duration_us": 1500,
+    ///   "determinism": { "seed": 42, "now": 1700000000 }
+    /// }
+    /// ```
+    ///
+    /// If the eval ran past its execution budget, `value` and `exception` are
+    /// both `null` and `timeout_us` holds the budget. Otherwise, exactly one of
+    /// `value` and `exception` is `null`.
+    ///
+    /// `determinism` holds the inputs needed to reproduce a deterministic eval
+    /// and is `null` otherwise. See [`Determinism::write_json`].
+    ///
+    /// `spans` holds stdout and stderr with ANSI escape sequences interpreted.
+    /// See [`ansi::Span::write_json`] for the shape of styled spans and
+    /// [`Frame::write_json`] for the shape of backtrace frames.
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        f.write_str(r#"{"stdout":"#)?;
+        json::write_string(&mut f, &self.stdout)?;
+        f.write_str(r#","stderr":"#)?;
+        json::write_string(&mut f, &self.stderr)?;
+        f.write_str(r#","transcript":["#)?;
+        for (idx, chunk) in self.transcript().iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            chunk.write_json(&mut f)?;
+        }
+        f.write_str("]")?;
+        f.write_str(r#","spans":{"stdout":"#)?;
+        ansi::write_json(&mut f, &ansi::parse(&self.stdout))?;
+        f.write_str(r#","stderr":"#)?;
+        ansi::write_json(&mut f, &ansi::parse(&self.stderr))?;
+        f.write_str("}")?;
+
+        match self.outcome {
+            Outcome::Value(ref value) => {
+                f.write_str(r#","value":{"inspect":"#)?;
+                json::write_string(&mut f, &value.inspect)?;
+                f.write_str(r#","class":"#)?;
+                json::write_string(&mut f, &value.class)?;
+                f.write_str(r#"},"exception":null"#)?;
+            }
+            Outcome::Exception(ref exc) => {
+                f.write_str(r#","value":null,"exception":{"class":"#)?;
+                json::write_string(&mut f, exc.class.as_bytes())?;
+                f.write_str(r#","message":"#)?;
+                json::write_string(&mut f, &exc.message)?;
+                f.write_str(r#","backtrace":"#)?;
+                json::write_string_array(&mut f, &exc.backtrace)?;
+                f.write_str(r#","frames":["#)?;
+                for (idx, frame) in exc.frames().iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(",")?;
+                    }
+                    frame.write_json(&mut f)?;
+                }
+                f.write_str("]}")?;
+            }
+            Outcome::Timeout(_) => f.write_str(r#","value":null,"exception":null"#)?,
+        }
+
+        if let Outcome::Timeout(limit) = self.outcome {
+            write!(f, r#","timeout_us":{}"#, limit.as_micros())?;
+        } else {
+            f.write_str(r#","timeout_us":null"#)?;
+        }
+
+        let micros = self.duration.as_micros();
+        write!(f, r#","duration_us":{micros},"determinism":"#)?;
+        match self.determinism {
+            Some(ref determinism) => determinism.write_json(&mut f)?,
+            None => f.write_str("null")?,
+        }
+        f.write_str("}")
+    }
+}
+
+fn write_bytes<W>(mut f: W, bytes: &[u8], binary: BinaryFormat) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    if let Ok(s) = str::from_utf8(bytes) {
+        return f.write_str(s);
+    }
+    match binary {
+        BinaryFormat::Replace => f.write_str(&String::from_utf8_lossy(bytes)),
+        BinaryFormat::Escape | BinaryFormat::HexDump => format_debug_escape_into(f, bytes),
+    }
+}
+
+/// Write captured output line by line, or as a hex dump if it is binary and
+/// `binary` is [`BinaryFormat::HexDump`].
+fn write_output<W>(mut f: W, bytes: &[u8], binary: BinaryFormat) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    if binary == BinaryFormat::HexDump && str::from_utf8(bytes).is_err() {
+        return write_hex_dump(f, bytes);
+    }
+    for line in bytes.lines() {
+        write_line(&mut f, line, binary)?;
+    }
+    Ok(())
+}
+
+/// Write `bytes` as rows of 16 bytes with an offset, hex bytes, and the
+/// printable ASCII characters, like `hexdump -C`.
+fn write_hex_dump<W>(mut f: W, bytes: &[u8]) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        write!(f, "{:08x} ", row * 16)?;
+        for idx in 0..16 {
+            if idx == 8 {
+                f.write_char(' ')?;
+            }
+            match chunk.get(idx) {
+                Some(byte) => write!(f, " {byte:02x}")?,
+                None => f.write_str("   ")?,
+            }
+        }
+        f.write_str("  |")?;
+        for &byte in chunk {
+            let ch = if byte.is_ascii_graphic() || byte == b' ' {
+                char::from(byte)
+            } else {
+                '.'
+            };
+            f.write_char(ch)?;
+        }
+        f.write_str("|\n")?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_duration<W>(mut f: W, duration: Duration) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    if duration.subsec_nanos() == 0 {
+        write!(f, "{}s", duration.as_secs())
+    } else {
+        write!(f, "{}ms", duration.as_millis())
+    }
+}
+
+fn write_line<W>(mut f: W, line: &[u8], binary: BinaryFormat) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    write_bytes(&mut f, line, binary)?;
+    f.write_str("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        BinaryFormat, ExceptionReport, Frame, Layout, Outcome, Report, TextOptions, ValueReport,
+    };
+    use crate::determinism::Determinism;
+    use crate::output::{Chunk, Stream};
+
+    fn value_report() -> Report {
+        Report {
+            stdout: b"hello\nworld\n".to_vec(),
+            stderr: b"warning\n".to_vec(),
+            outcome: Outcome::Value(ValueReport {
+                inspect: b"nil".to_vec(),
+                class: b"NilClass".to_vec(),
+            }),
+            duration: Duration::from_millis(3),
+            ..Report::default()
+        }
+    }
+
+    fn exception_report() -> Report {
+        Report {
+            outcome: Outcome::Exception(ExceptionReport::new(
+                String::from("RuntimeError"),
+                b"boom".to_vec(),
+                vec![
+                    b"(playground):2:in explode".to_vec(),
+                    b"/artichoke/virtual_root/src/lib/json.rb:10".to_vec(),
+                    b"(playground):4".to_vec(),
+                ],
+            )),
+            ..Report::default()
+        }
+    }
+
+    #[test]
+    fn text_report_with_value() {
+        let mut s = String::new();
+        value_report().write_text(&mut s).unwrap();
+        assert_eq!(s, "hello\nworld\n--- stderr:warning\n=> nil\n");
+    }
+
+    #[test]
+    fn text_report_interleaved() {
+        let report = Report {
+            stdout: b"a\nc\n".to_vec(),
+            stderr: b"b\n".to_vec(),
+            transcript: vec![
+                Chunk {
+                    stream: Stream::Stdout,
+                    bytes: b"a\n".to_vec(),
+                },
+                Chunk {
+                    stream: Stream::Stderr,
+                    bytes: b"b\n".to_vec(),
+                },
+                Chunk {
+                    stream: Stream::Stdout,
+                    bytes: b"c\n".to_vec(),
+                },
+            ],
+            ..value_report()
+        };
+        let mut s = String::new();
+        report
+            .write_text_with_layout(&mut s, Layout::Interleaved)
+            .unwrap();
+        assert_eq!(s, "a\n--- stderr:b\n--- stdout:c\n=> nil\n");
+
+        let mut s = String::new();
+        report
+            .write_text_with_layout(&mut s, Layout::Grouped)
+            .unwrap();
+        assert_eq!(s, "a\nc\n--- stderr:b\n=> nil\n");
+    }
+
+    #[test]
+    fn text_report_interleaved_without_transcript() {
+        let mut s = String::new();
+        value_report()
+            .write_text_with_layout(&mut s, Layout::Interleaved)
+            .unwrap();
+        assert_eq!(s, "hello\nworld\n--- stderr:warning\n=> nil\n");
+    }
+
+    #[test]
+    fn text_report_with_exception() {
+        let mut s = String::new();
+        exception_report().write_text(&mut s).unwrap();
+        assert_eq!(
+            s,
+            "RuntimeError (boom)\n\tfrom (playground):2:in explode\n\tfrom (playground):4"
+        );
+    }
+
+    #[test]
+    fn parse_frames() {
+        let frame = Frame::parse(b"lib/helper.rb:12:in `Helper.greet'");
+        assert_eq!(frame.file, b"lib/helper.rb");
+        assert_eq!(frame.line, Some(12));
+        assert_eq!(frame.method.as_deref(), Some(&b"Helper.greet"[..]));
+        assert!(frame.is_playground);
+        assert!(!frame.is_internal);
+
+        let frame = Frame::parse(b"docs/intro.rb:11");
+        assert!(frame.is_playground);
+
+        let frame = Frame::parse(b"(playground)");
+        assert_eq!(frame.file, b"(playground)");
+        assert_eq!(frame.line, None);
+        assert!(frame.is_playground);
+
+        let frame = Frame::parse(b"playground/stdin.rb:40:in gets");
+        assert!(frame.is_internal);
+        assert!(!frame.is_playground);
+    }
+
+    #[test]
+    fn text_report_with_timeout() {
+        let report = Report {
+            stdout: b"tick\n".to_vec(),
+            outcome: Outcome::Timeout(Duration::from_secs(5)),
+            ..Report::default()
+        };
+        let mut s = String::new();
+        report.write_text(&mut s).unwrap();
+        assert_eq!(s, "tick\nExecution exceeded 5s\n");
+
+        let report = Report {
+            outcome: Outcome::Timeout(Duration::from_millis(1500)),
+            ..Report::default()
+        };
+        let mut s = String::new();
+        report.write_text(&mut s).unwrap();
+        assert_eq!(s, "Execution exceeded 1500ms\n");
+    }
+
+    #[test]
+    fn text_report_escapes_invalid_utf8() {
+        let report = Report {
+            stdout: b"\xFF\n".to_vec(),
+            ..Report::default()
+        };
+        let mut s = String::new();
+        report.write_text(&mut s).unwrap();
+        assert_eq!(s, "\\xFF\n=> ");
+    }
+
+    #[test]
+    fn text_report_replaces_invalid_utf8() {
+        let report = Report {
+            stdout: b"caf\xC3\n\xFF\n".to_vec(),
+            ..Report::default()
+        };
+        let options = TextOptions {
+            binary: BinaryFormat::Replace,
+            ..TextOptions::default()
+        };
+        let mut s = String::new();
+        report.write_text_with_options(&mut s, options).unwrap();
+        assert_eq!(s, "caf\u{FFFD}\n\u{FFFD}\n=> ");
+    }
+
+    #[test]
+    fn text_report_hex_dumps_binary_output() {
+        let report = Report {
+            stdout: b"\xFFA\n".to_vec(),
+            stderr: b"plain\n".to_vec(),
+            ..Report::default()
+        };
+        let options = TextOptions {
+            binary: BinaryFormat::HexDump,
+            ..TextOptions::default()
+        };
+        let mut s = String::new();
+        report.write_text_with_options(&mut s, options).unwrap();
+        assert_eq!(
+            s,
+            "00000000  ff 41 0a                                          |.A.|\n--- stderr:plain\n=> "
+        );
+
+        let report = Report {
+            stdout: (0..16).chain([0xFF, b'A']).collect(),
+            ..Report::default()
+        };
+        let mut s = String::new();
+        report.write_text_with_options(&mut s, options).unwrap();
+        assert_eq!(
+            s,
+            concat!(
+                "00000000  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|\n",
+                "00000010  ff 41                                             |.A|\n",
+                "=> ",
+            )
+        );
+    }
+
+    #[test]
+    fn json_report_with_value() {
+        let mut s = String::new();
+        value_report().write_json(&mut s).unwrap();
+        assert_eq!(
+            s,
+            concat!(
+                r#"{"stdout":"hello\nworld\n","stderr":"warning\n","#,
+                r#""transcript":[{"stream":"stdout","bytes":"hello\nworld\n"},{"stream":"stderr","bytes":"warning\n"}],"#,
+                r#""spans":{"stdout":[{"text":"hello\nworld\n","fg":null,"bg":null,"bold":false,"underline":false}],"#,
+                r#""stderr":[{"text":"warning\n","fg":null,"bg":null,"bold":false,"underline":false}]},"#,
+                r#""value":{"inspect":"nil","class":"NilClass"},"exception":null,"timeout_us":null,"duration_us":3000,"determinism":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn json_report_with_styled_output() {
+        let report = Report {
+            stdout: b"\x1b[31mred\x1b[0m\n".to_vec(),
+            ..Report::default()
+        };
+        let mut s = String::new();
+        report.write_json(&mut s).unwrap();
+        assert!(s.contains(
+            r#""spans":{"stdout":[{"text":"red","fg":1,"bg":null,"bold":false,"underline":false},{"text":"\n","fg":null,"bg":null,"bold":false,"underline":false}],"stderr":[]}"#
+        ));
+    }
+
+    #[test]
+    fn json_report_with_timeout() {
+        let report = Report {
+            outcome: Outcome::Timeout(Duration::from_secs(5)),
+            duration: Duration::from_secs(6),
+            ..Report::default()
+        };
+        let mut s = String::new();
+        report.write_json(&mut s).unwrap();
+        assert_eq!(
+            s,
+            r#"{"stdout":"","stderr":"","transcript":[],"spans":{"stdout":[],"stderr":[]},"value":null,"exception":null,"timeout_us":5000000,"duration_us":6000000,"determinism":null}"#
+        );
+    }
+
+    #[test]
+    fn json_report_with_determinism() {
+        let report = Report {
+            determinism: Some(Determinism {
+                seed: 42,
+                now: 1_700_000_000,
+            }),
+            ..Report::default()
+        };
+        let mut s = String::new();
+        report.write_json(&mut s).unwrap();
+        assert!(s.ends_with(r#""duration_us":0,"determinism":{"seed":42,"now":1700000000}}"#));
+    }
+
+    #[test]
+    fn json_report_with_exception() {
+        let mut s = String::new();
+        exception_report().write_json(&mut s).unwrap();
+        assert_eq!(
+            s,
+            concat!(
+                r#"{"stdout":"","stderr":"","transcript":[],"spans":{"stdout":[],"stderr":[]},"value":null,"#,
+                r#""exception":{"class":"RuntimeError","message":"boom","#,
+                r#""backtrace":["(playground):2:in explode","/artichoke/virtual_root/src/lib/json.rb:10","(playground):4"],"#,
+                r#""frames":[{"file":"(playground)","line":2,"method":"explode","playground":true,"internal":false},"#,
+                r#"{"file":"/artichoke/virtual_root/src/lib/json.rb","line":10,"method":null,"playground":false,"internal":true},"#,
+                r#"{"file":"(playground)","line":4,"method":null,"playground":true,"internal":false}]},"#,
+                r#""timeout_us":null,"duration_us":0,"determinism":null}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! In-memory standard input for playground evals.
+//!
+//! Artichoke does not implement `IO`, so the playground installs a small Ruby
+//! prelude which defines `STDIN`, `$stdin`, `ARGF`, and `Kernel#gets` on top of
+//! a buffer supplied by the frontend.
+
+use artichoke::prelude::*;
+
+use crate::prelude::Prelude;
+
+/// Ruby source for the stdin prelude.
+const PRELUDE: Prelude = Prelude {
+    path: "playground/stdin.rb",
+    source: br#"
+module Playground
+  # An in-memory standard input stream backed by a buffer supplied by the
+  # playground frontend.
+  #
+  # Like `IO`, the stream position and the limits passed to `gets` and
+  # `read` are counted in bytes.
+  class Stdin
+    include Enumerable
+
+    attr_accessor :lineno
+
+    def initialize(input = '')
+      reopen(input)
+    end
+
+    def reopen(input)
+      @input = input.to_s
+      @pos = 0
+      @lineno = 0
+      self
+    end
+
+    def eof?
+      @pos >= @input.bytesize
+    end
+    alias eof eof?
+
+    # Read the next line.
+    #
+    # Like `IO#gets`, this accepts an optional separator and an optional
+    # byte limit, in either `gets(sep, limit)` or `gets(limit)` form. A
+    # `nil` separator reads the rest of the input and an empty separator
+    # reads a paragraph, skipping the blank lines around it. A limit which
+    # ends inside a multibyte character reads the rest of the character.
+    def gets(*args)
+      sep, limit = line_args(*args)
+      return nil if eof?
+      return '' if limit == 0
+
+      paragraph = sep == ''
+      @pos += 1 while paragraph && @input.byteslice(@pos, 1) == "\n"
+      return nil if eof?
+
+      sep = "\n\n" if paragraph
+      rest = remaining
+      stop = sep.nil? ? nil : rest.index(sep)
+      line = stop.nil? ? rest : rest[0, stop] + sep
+      line = truncate(line, limit) if limit
+      @pos += line.bytesize
+      @pos += 1 while paragraph && line.end_with?(sep) && @input.byteslice(@pos, 1) == "\n"
+      @lineno += 1
+      $_ = line
+    end
+
+    def readline(*args)
+      line = gets(*args)
+      raise EOFError, 'end of file reached' if line.nil?
+
+      line
+    end
+
+    def each_line(*args)
+      return to_enum(:each_line, *args) unless block_given?
+
+      _, limit = line_args(*args)
+      raise ArgumentError, 'invalid limit: 0 for each_line' if limit == 0
+
+      while (line = gets(*args))
+        yield line
+      end
+      self
+    end
+    alias each each_line
+
+    def readlines(*args)
+      _, limit = line_args(*args)
+      raise ArgumentError, 'invalid limit: 0 for readlines' if limit == 0
+
+      each_line(*args).to_a
+    end
+
+    def read(length = nil)
+      if length.nil?
+        rest = remaining
+        @pos = @input.bytesize
+        return rest
+      end
+      return '' if length.zero?
+      return nil if eof?
+
+      chunk = @input.byteslice(@pos, length)
+      @pos += chunk.bytesize
+      chunk
+    end
+
+    def getc
+      return nil if eof?
+
+      ch = remaining[0]
+      @pos += ch.bytesize
+      ch
+    end
+
+    def rewind
+      @pos = 0
+      @lineno = 0
+      0
+    end
+
+    def tty?
+      false
+    end
+    alias isatty tty?
+
+    private
+
+    def remaining
+      @input.byteslice(@pos, @input.bytesize - @pos) || ''
+    end
+
+    # The shortest prefix of `line` made of whole characters which is at
+    # least `limit` bytes long.
+    def truncate(line, limit)
+      return line if line.bytesize <= limit
+
+      count = 0
+      bytes = 0
+      while bytes < limit
+        bytes += line[count].bytesize
+        count += 1
+      end
+      line[0, count]
+    end
+
+    def line_args(sep = "\n", limit = nil)
+      if limit.nil? && sep.is_a?(Integer)
+        limit = sep
+        sep = "\n"
+      end
+      sep = sep.to_str unless sep.nil?
+      limit = limit.to_int unless limit.nil?
+      limit = nil if limit && limit < 0
+      [sep, limit]
+    end
+  end
+end
+
+STDIN = Playground::Stdin.new
+ARGF = STDIN
+$stdin = STDIN
+
+module Kernel
+  def gets(*args)
+    $stdin.gets(*args)
+  end
+
+  def readline(*args)
+    $stdin.readline(*args)
+  end
+
+  def readlines(*args)
+    $stdin.readlines(*args)
+  end
+end
+"#,
+};
+
+/// Install the stdin prelude on the given interpreter and replace the contents
+/// of `STDIN` with `input`.
+///
+/// `$stdin` is reset to `STDIN` in case a previous eval reassigned it.
+///
+/// # Errors
+///
+/// If the prelude fails to load or the input buffer cannot be attached, an
+/// error is returned.
+pub fn attach(interp: &mut Artichoke, input: &[u8]) -> Result<(), Error> {
+    PRELUDE.load(interp)?;
+
+    let input = interp.convert_mut(input);
+    let stdin = interp.eval(b"STDIN")?;
+    stdin.funcall(interp, "reopen", &[input], None)?;
+    interp.set_global_variable(&b"$stdin"[..], &stdin)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use artichoke::prelude::*;
+
+    use super::attach;
+
+    fn eval(input: &[u8], code: &[u8]) -> Vec<u8> {
+        let mut interp = artichoke::interpreter().unwrap();
+        attach(&mut interp, input).unwrap();
+        let value = interp.eval(code).unwrap();
+        let inspect = value.inspect(&mut interp);
+        interp.close();
+        inspect
+    }
+
+    #[test]
+    fn gets_splits_lines() {
+        let lines = eval(b"a\nb\nc", b"[gets, gets, gets, gets]");
+        assert_eq!(lines, br#"["a\n", "b\n", "c", nil]"#);
+
+        let lines = eval(b"a,b", b"[gets(','), gets(nil), gets]");
+        assert_eq!(lines, br#"["a,", "b", nil]"#);
+    }
+
+    #[test]
+    fn gets_reads_paragraphs() {
+        let input = b"\n\npara one\nstill one\n\n\n\npara two\n";
+        let paragraphs = eval(input, b"[gets(''), gets(''), gets('')]");
+        assert_eq!(
+            paragraphs,
+            br#"["para one\nstill one\n\n", "para two\n", nil]"#
+        );
+
+        let paragraphs = eval(input, b"STDIN.readlines('')");
+        assert_eq!(paragraphs, br#"["para one\nstill one\n\n", "para two\n"]"#);
+
+        let count = eval(b"a\n\nb", b"n = 0; STDIN.each_line('') { n += 1 }; n");
+        assert_eq!(count, b"2");
+    }
+
+    #[test]
+    fn gets_honors_limits() {
+        let lines = eval(
+            b"abcdef\ngh\n",
+            b"[gets(4), gets(4), gets(\"\\n\", 1), gets(0), gets(-1)]",
+        );
+        assert_eq!(lines, br#"["abcd", "ef\n", "g", "", "h\n"]"#);
+
+        let lines = eval(b"abc", b"STDIN.readlines(2)");
+        assert_eq!(lines, br#"["ab", "c"]"#);
+
+        let err = eval(
+            b"abc",
+            b"begin; STDIN.readlines(0); rescue ArgumentError => e; e.message; end",
+        );
+        assert_eq!(err, br#""invalid limit: 0 for readlines""#);
+    }
+
+    #[test]
+    fn limits_count_bytes() {
+        let input = "héllo\nwörld\n".as_bytes();
+        let lines = eval(input, b"[gets(2), gets(2), STDIN.read(3), gets]");
+        assert_eq!(lines, r#"["hé", "ll", "o\nw", "örld\n"]"#.as_bytes());
+
+        let chars = eval(input, b"[STDIN.getc, STDIN.getc, STDIN.read.bytesize]");
+        assert_eq!(chars, r#"["h", "é", 11]"#.as_bytes());
+    }
+}
@@ -1,6 +1,122 @@
 //! JS/Rust string interop utilities.
 
-use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::ptr;
+
+use crate::json;
+
+/// Number of bits of a handle used to store the slot index.
+const INDEX_BITS: u32 = 24;
+
+/// Mask for extracting the slot index from a handle.
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+
+/// The maximum number of slots in a heap.
+const MAX_SLOTS: u32 = INDEX_MASK + 1;
+
+/// Error returned when a handle does not refer to a live string in a
+/// [`Heap`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum HeapError {
+    /// The handle referred to a string which has since been freed.
+    Stale,
+    /// The handle was never returned by the heap.
+    Unallocated,
+    /// The byte index is past the end of the string.
+    OutOfBounds,
+    /// Every slot in the heap is either live or retired.
+    Exhausted,
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stale => f.write_str("handle refers to a freed string"),
+            Self::Unallocated => f.write_str("handle was never allocated"),
+            Self::OutOfBounds => f.write_str("index out of bounds"),
+            Self::Exhausted => f.write_str("string heap exhausted"),
+        }
+    }
+}
+
+impl error::Error for HeapError {}
+
+/// A snapshot of the memory held by a [`Heap`].
+#[derive(Default, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The number of live strings.
+    pub live: usize,
+    /// The total byte length of every live string.
+    pub bytes: usize,
+    /// The highest number of strings which have been live at once.
+    pub peak_live: usize,
+    /// The highest total byte length of the strings which have been live at
+    /// once.
+    pub peak_bytes: usize,
+}
+
+impl HeapStats {
+    /// Serialize this snapshot as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// { "live": 2, "bytes": 120, "peak_live": 4, "peak_bytes": 512 }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        write!(
+            f,
+            r#"{{"live":{},"bytes":{},"peak_live":{},"peak_bytes":{}}}"#,
+            self.live, self.bytes, self.peak_live, self.peak_bytes
+        )
+    }
+}
+
+/// A live string in a [`Heap`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct LiveSlot {
+    /// The handle of the string.
+    pub ptr: u32,
+    /// The byte length of the string.
+    pub len: usize,
+    /// The allocation site tag of the string, if any.
+    ///
+    /// See [`Heap::set_tag`].
+    pub tag: Option<&'static str>,
+}
+
+impl LiveSlot {
+    /// Serialize this slot as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// { "ptr": 1, "len": 12, "tag": "eval" }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        write!(f, r#"{{"ptr":{},"len":{},"tag":"#, self.ptr, self.len)?;
+        match self.tag {
+            Some(tag) => json::write_string(&mut f, tag.as_bytes())?,
+            None => f.write_str("null")?,
+        }
+        f.write_str("}")
+    }
+}
 
 /// Persistent heap for byte strings.
 ///
@@ -12,11 +128,54 @@ use std::collections::HashMap;
 /// by JS code and then read out of the heap by Rust code so it can be passed to
 /// an [`Artichoke`] instance.
 ///
+/// Handles returned by the heap encode the index of the slot a string is
+/// stored in and the generation of that slot, which is incremented each time
+/// the slot is freed. The `try_` accessors use the generation to distinguish
+/// reads of freed strings from reads of handles the heap never returned. The
+/// infallible accessors treat both as an empty string, or panic if
+/// [`set_panic_on_misuse`] is enabled, which also applies to out of bounds
+/// reads.
+///
+/// Freed slots are recycled by later allocations. A slot whose generation is
+/// exhausted is retired instead of recycled, so a handle is never aliased by a
+/// later allocation. The heap is exhausted once every slot is live or retired.
+///
+/// The first string allocated in a heap always has handle `0`.
+///
 /// [`Artichoke`]: artichoke::Artichoke
-#[derive(Debug, Clone, Default)]
+/// [`set_panic_on_misuse`]: Self::set_panic_on_misuse
+#[derive(Debug, Clone)]
 pub struct Heap {
-    memory: HashMap<u32, Vec<u8>>,
-    next_free: u32,
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    live: usize,
+    bytes: usize,
+    peak_live: usize,
+    peak_bytes: usize,
+    max_slots: u32,
+    panic_on_misuse: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    generation: u8,
+    bytes: Option<Vec<u8>>,
+    tag: Option<&'static str>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            live: 0,
+            bytes: 0,
+            peak_live: 0,
+            peak_bytes: 0,
+            max_slots: MAX_SLOTS,
+            panic_on_misuse: false,
+        }
+    }
 }
 
 impl Heap {
@@ -32,17 +191,14 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn new() -> Self {
-        Self {
-            memory: HashMap::new(),
-            next_free: 0,
-        }
+        Self::default()
     }
 
     /// Creates an empty string heap with at least the specified capacity.
     ///
     /// The string heap will be able to hold at least `capacity` elements without
     /// reallocating. This method is allowed to allocate for more elements than
-    /// `capacity`. If `capacity` is 0, the hash map will not allocate.
+    /// `capacity`. If `capacity` is 0, the heap will not allocate.
     ///
     /// # Examples
     ///
@@ -55,8 +211,8 @@ impl Heap {
     #[must_use]
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            memory: HashMap::with_capacity(capacity),
-            next_free: 0,
+            slots: Vec::with_capacity(capacity),
+            ..Self::default()
         }
     }
 
@@ -75,7 +231,7 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn capacity(&self) -> usize {
-        self.memory.capacity()
+        self.slots.capacity()
     }
 
     /// Returns the number of strings in the heap.
@@ -92,7 +248,7 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn len(&self) -> usize {
-        self.memory.len()
+        self.live
     }
 
     /// Returns `true` if the heap contains no strings.
@@ -109,7 +265,123 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn is_empty(&self) -> bool {
-        self.memory.is_empty()
+        self.live == 0
+    }
+
+    /// Returns a snapshot of the memory held by the heap.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// let _ = a.allocate("Ruby".to_owned());
+    /// a.free(sym);
+    ///
+    /// let stats = a.stats();
+    /// assert_eq!(stats.live, 1);
+    /// assert_eq!(stats.bytes, 4);
+    /// assert_eq!(stats.peak_live, 2);
+    /// assert_eq!(stats.peak_bytes, 8);
+    /// ```
+    #[must_use]
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            live: self.live,
+            bytes: self.bytes,
+            peak_live: self.peak_live,
+            peak_bytes: self.peak_bytes,
+        }
+    }
+
+    /// Returns every live string in the heap, in slot order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// a.set_tag(sym, "example");
+    ///
+    /// let live = a.live_slots();
+    /// assert_eq!(live.len(), 1);
+    /// assert_eq!(live[0].ptr, sym);
+    /// assert_eq!(live[0].len, 4);
+    /// assert_eq!(live[0].tag, Some("example"));
+    /// ```
+    #[must_use]
+    pub fn live_slots(&self) -> Vec<LiveSlot> {
+        self.slots
+            .iter()
+            .zip(0..)
+            .filter_map(|(slot, index)| {
+                let bytes = slot.bytes.as_ref()?;
+                Some(LiveSlot {
+                    ptr: encode(index, slot.generation),
+                    len: bytes.len(),
+                    tag: slot.tag,
+                })
+            })
+            .collect()
+    }
+
+    /// Label the string identified by `ptr` with the site that allocated it.
+    ///
+    /// Tags are reported by [`live_slots`](Self::live_slots) to help track
+    /// down strings which were never freed. A slot's tag is cleared when it is
+    /// freed.
+    ///
+    /// If `ptr` refers to a string not present in the heap, this function is a
+    /// no-op.
+    pub fn set_tag(&mut self, ptr: u32, tag: &'static str) {
+        if let Err(err) = self.lookup(ptr) {
+            self.misuse(ptr, err);
+            return;
+        }
+        let (index, _) = decode(ptr);
+        self.slots[index as usize].tag = Some(tag);
+    }
+
+    /// Returns `true` if misusing the infallible accessors panics.
+    ///
+    /// See [`set_panic_on_misuse`](Self::set_panic_on_misuse).
+    #[must_use]
+    pub fn panics_on_misuse(&self) -> bool {
+        self.panic_on_misuse
+    }
+
+    /// Set whether misusing the infallible accessors panics.
+    ///
+    /// By default, the infallible accessors treat a stale or never-allocated
+    /// handle as an empty string and [`string_getch`](Self::string_getch)
+    /// returns `0` for an out of bounds index. This is forgiving of bugs in
+    /// foreign code but hides them: a read of a freed string produces empty
+    /// output instead of an error. Enabling this debug mode turns every such
+    /// call into a panic, which reports the [`HeapError`] the corresponding
+    /// `try_` accessor would have returned.
+    ///
+    /// Unwinding out of an `extern "C"` export is undefined behavior, so the
+    /// FFI catches the panic and aborts the module at the first misuse.
+    /// Callers which want to recover from misuse should use the `try_`
+    /// accessors.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// a.set_panic_on_misuse(true);
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// a.free(sym);
+    /// let _ = a.string_getlen(sym); // panics
+    /// ```
+    pub fn set_panic_on_misuse(&mut self, enabled: bool) {
+        self.panic_on_misuse = enabled;
     }
 
     /// Allocate a slot in the heap and store a UTF-8 string in it.
@@ -119,6 +391,11 @@ impl Heap {
     ///
     /// Every call to `allocate` is guaranteed to return a unique value.
     ///
+    /// # Panics
+    ///
+    /// If the heap is exhausted, this function panics. See
+    /// [`try_allocate`](Self::try_allocate) for a fallible version.
+    ///
     /// # Examples
     ///
     /// ```
@@ -129,10 +406,160 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn allocate(&mut self, s: String) -> u32 {
-        let ptr = self.next_free;
-        self.next_free += 1;
-        self.memory.insert(ptr, s.into_bytes());
-        ptr
+        self.try_allocate(s).expect("string heap exhausted")
+    }
+
+    /// Allocate a slot in the heap and store a UTF-8 string in it.
+    ///
+    /// Every successful call to `try_allocate` is guaranteed to return a
+    /// unique value.
+    ///
+    /// # Errors
+    ///
+    /// If every slot in the heap is live or retired, [`HeapError::Exhausted`]
+    /// is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.try_allocate("Wasm".to_owned()).unwrap();
+    /// assert_eq!(a.string(sym), b"Wasm");
+    /// ```
+    pub fn try_allocate(&mut self, s: String) -> Result<u32, HeapError> {
+        self.insert(s.into_bytes())
+    }
+
+    /// Allocate a slot in the heap and fill it with `len` zero bytes.
+    ///
+    /// This function is used to reserve a buffer that foreign code can fill in
+    /// bulk with a single copy into Wasm linear memory instead of appending
+    /// one byte at a time. See [`string_as_mut_ptr`].
+    ///
+    /// Every call to `reserve` is guaranteed to return a unique value.
+    ///
+    /// # Panics
+    ///
+    /// If the heap is exhausted, this function panics. See
+    /// [`try_reserve`](Self::try_reserve) for a fallible version.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.reserve(4);
+    /// assert_eq!(a.string(sym), &[0, 0, 0, 0]);
+    /// ```
+    ///
+    /// [`string_as_mut_ptr`]: Self::string_as_mut_ptr
+    #[must_use]
+    pub fn reserve(&mut self, len: usize) -> u32 {
+        self.try_reserve(len).expect("string heap exhausted")
+    }
+
+    /// Allocate a slot in the heap and fill it with `len` zero bytes.
+    ///
+    /// See [`reserve`](Self::reserve) for more details.
+    ///
+    /// # Errors
+    ///
+    /// If every slot in the heap is live or retired, [`HeapError::Exhausted`]
+    /// is returned.
+    pub fn try_reserve(&mut self, len: usize) -> Result<u32, HeapError> {
+        self.insert(vec![0; len])
+    }
+
+    /// Allocate a slot in the heap and store a byte string, which may not be
+    /// UTF-8, in it.
+    ///
+    /// Every call to `allocate_bytes` is guaranteed to return a unique value.
+    ///
+    /// # Panics
+    ///
+    /// If the heap is exhausted, this function panics. See
+    /// [`try_allocate_bytes`](Self::try_allocate_bytes) for a fallible
+    /// version.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate_bytes(b"\x04\x08[\x06i\x06".to_vec());
+    /// assert_eq!(a.string(sym), b"\x04\x08[\x06i\x06");
+    /// ```
+    #[must_use]
+    pub fn allocate_bytes(&mut self, bytes: Vec<u8>) -> u32 {
+        self.try_allocate_bytes(bytes)
+            .expect("string heap exhausted")
+    }
+
+    /// Allocate a slot in the heap and store a byte string, which may not be
+    /// UTF-8, in it.
+    ///
+    /// # Errors
+    ///
+    /// If every slot in the heap is live or retired, [`HeapError::Exhausted`]
+    /// is returned.
+    pub fn try_allocate_bytes(&mut self, bytes: Vec<u8>) -> Result<u32, HeapError> {
+        self.insert(bytes)
+    }
+
+    fn insert(&mut self, bytes: Vec<u8>) -> Result<u32, HeapError> {
+        let len = bytes.len();
+        let ptr = if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.bytes = Some(bytes);
+            encode(index, slot.generation)
+        } else {
+            let index = u32::try_from(self.slots.len())
+                .ok()
+                .filter(|&index| index < self.max_slots)
+                .ok_or(HeapError::Exhausted)?;
+            self.slots.push(Slot {
+                generation: 0,
+                bytes: Some(bytes),
+                tag: None,
+            });
+            encode(index, 0)
+        };
+        self.live += 1;
+        self.peak_live = self.peak_live.max(self.live);
+        self.grow(len);
+        Ok(ptr)
+    }
+
+    fn grow(&mut self, additional: usize) {
+        self.bytes += additional;
+        self.peak_bytes = self.peak_bytes.max(self.bytes);
+    }
+
+    fn shrink(&mut self, removed: usize) {
+        self.bytes -= removed;
+    }
+
+    /// Empty the slot at `index` and recycle it, or retire it if its
+    /// generation is exhausted.
+    fn release(&mut self, index: u32) {
+        let slot = &mut self.slots[index as usize];
+        let Some(bytes) = slot.bytes.take() else {
+            return;
+        };
+        slot.tag = None;
+        self.live -= 1;
+        self.bytes -= bytes.len();
+        slot.generation += 1;
+        // No handle is issued with generation `u8::MAX`, so no handle collides
+        // with the error codes the FFI layer reserves at the top of the `u32`
+        // range.
+        if slot.generation < u8::MAX {
+            self.free.push(index);
+        }
     }
 
     /// Free the string in the heap identified by the given pointer-sized value.
@@ -150,7 +577,34 @@ impl Heap {
     /// a.free(sym);
     /// ```
     pub fn free(&mut self, ptr: u32) {
-        self.memory.remove(&ptr);
+        if let Err(err) = self.lookup(ptr) {
+            self.misuse(ptr, err);
+            return;
+        }
+        let (index, _) = decode(ptr);
+        self.release(index);
+    }
+
+    /// Free every string in the heap.
+    ///
+    /// Pointers handed out before the heap was cleared are not reused by
+    /// subsequent allocations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// a.clear();
+    /// assert!(a.is_empty());
+    /// assert_ne!(a.allocate("Wasm".to_owned()), sym);
+    /// ```
+    pub fn clear(&mut self) {
+        for index in 0..self.slots.len() {
+            self.release(index as u32);
+        }
     }
 
     /// Retrieve the byte contents of the string in the heap identified by
@@ -171,7 +625,38 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn string(&self, ptr: u32) -> &[u8] {
-        self.memory.get(&ptr).map(Vec::as_slice).unwrap_or_default()
+        match self.try_string(ptr) {
+            Ok(s) => s,
+            Err(err) => {
+                self.misuse(ptr, err);
+                &[]
+            }
+        }
+    }
+
+    /// Retrieve the byte contents of the string in the heap identified by
+    /// `ptr`.
+    ///
+    /// # Errors
+    ///
+    /// If `ptr` refers to a string which has been freed, [`HeapError::Stale`]
+    /// is returned. If `ptr` was never returned by this heap,
+    /// [`HeapError::Unallocated`] is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::{Heap, HeapError};
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// assert_eq!(a.try_string(sym), Ok(&b"Wasm"[..]));
+    /// a.free(sym);
+    /// assert_eq!(a.try_string(sym), Err(HeapError::Stale));
+    /// assert_eq!(a.try_string(u32::MAX), Err(HeapError::Unallocated));
+    /// ```
+    pub fn try_string(&self, ptr: u32) -> Result<&[u8], HeapError> {
+        self.lookup(ptr).map(Vec::as_slice)
     }
 
     /// Retrieve the byte length of the string in the heap identified by `ptr`.
@@ -191,11 +676,31 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn string_getlen(&self, ptr: u32) -> u32 {
-        if let Some(s) = self.memory.get(&ptr) {
-            s.len() as u32
-        } else {
+        self.try_string_getlen(ptr).unwrap_or_else(|err| {
+            self.misuse(ptr, err);
             0
-        }
+        })
+    }
+
+    /// Retrieve the byte length of the string in the heap identified by `ptr`.
+    ///
+    /// # Errors
+    ///
+    /// See [`try_string`](Self::try_string).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::{Heap, HeapError};
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// assert_eq!(a.try_string_getlen(sym), Ok(4));
+    /// a.free(sym);
+    /// assert_eq!(a.try_string_getlen(sym), Err(HeapError::Stale));
+    /// ```
+    pub fn try_string_getlen(&self, ptr: u32) -> Result<u32, HeapError> {
+        self.lookup(ptr).map(|s| s.len() as u32)
     }
 
     /// Retrieve the byte at the given index of the string in the heap identified
@@ -219,17 +724,39 @@ impl Heap {
     /// ```
     #[must_use]
     pub fn string_getch(&self, ptr: u32, idx: u32) -> u8 {
-        let s = if let Some(s) = self.memory.get(&ptr) {
-            s
-        } else {
-            return 0;
-        };
-        let idx = if let Ok(idx) = usize::try_from(idx) {
-            idx
-        } else {
-            return 0;
-        };
-        s.get(idx).copied().unwrap_or_default()
+        self.try_string_getch(ptr, idx).unwrap_or_else(|err| {
+            self.misuse(ptr, err);
+            0
+        })
+    }
+
+    /// Retrieve the byte at the given index of the string in the heap identified
+    /// by `ptr`.
+    ///
+    /// # Errors
+    ///
+    /// If `idx` is out of bounds for the referenced string,
+    /// [`HeapError::OutOfBounds`] is returned. For errors related to `ptr`, see
+    /// [`try_string`](Self::try_string).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::{Heap, HeapError};
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// assert_eq!(a.try_string_getch(sym, 0), Ok(b'W'));
+    /// assert_eq!(a.try_string_getch(sym, 4), Err(HeapError::OutOfBounds));
+    /// assert_eq!(a.try_string_getch(sym + 1, 0), Err(HeapError::Unallocated));
+    /// ```
+    pub fn try_string_getch(&self, ptr: u32, idx: u32) -> Result<u8, HeapError> {
+        let s = self.lookup(ptr)?;
+        usize::try_from(idx)
+            .ok()
+            .and_then(|idx| s.get(idx))
+            .copied()
+            .ok_or(HeapError::OutOfBounds)
     }
 
     /// Modify the string in the heap identified by `ptr` by appending a byte
@@ -249,8 +776,491 @@ impl Heap {
     /// assert_eq!(a.string(sym), b"Wasm!");
     /// ```
     pub fn string_putch(&mut self, ptr: u32, ch: u8) {
-        if let Some(s) = self.memory.get_mut(&ptr) {
-            s.push(ch);
+        match self.lookup_mut(ptr) {
+            Ok(s) => {
+                s.push(ch);
+                self.grow(1);
+            }
+            Err(err) => self.misuse(ptr, err),
         }
     }
+
+    /// Retrieve `len` bytes of the string in the heap identified by `ptr`,
+    /// starting at byte `offset`.
+    ///
+    /// # Errors
+    ///
+    /// If the range extends past the end of the string,
+    /// [`HeapError::OutOfBounds`] is returned. For errors related to `ptr`, see
+    /// [`try_string`](Self::try_string).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::{Heap, HeapError};
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Artichoke".to_owned());
+    /// assert_eq!(a.string_slice(sym, 4, 5), Ok(&b"choke"[..]));
+    /// assert_eq!(a.string_slice(sym, 9, 0), Ok(&b""[..]));
+    /// assert_eq!(a.string_slice(sym, 4, 6), Err(HeapError::OutOfBounds));
+    /// ```
+    pub fn string_slice(&self, ptr: u32, offset: usize, len: usize) -> Result<&[u8], HeapError> {
+        let s = self.lookup(ptr)?;
+        offset
+            .checked_add(len)
+            .and_then(|end| s.get(offset..end))
+            .ok_or(HeapError::OutOfBounds)
+    }
+
+    /// Overwrite the bytes of the string in the heap identified by `ptr`,
+    /// starting at byte `offset`, with `bytes`.
+    ///
+    /// The length of the string is unchanged.
+    ///
+    /// # Errors
+    ///
+    /// If `bytes` would extend past the end of the string,
+    /// [`HeapError::OutOfBounds`] is returned and the string is not modified.
+    /// For errors related to `ptr`, see [`try_string`](Self::try_string).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::{Heap, HeapError};
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.reserve(4);
+    /// a.string_write_at(sym, 1, b"\xFF\x00").unwrap();
+    /// assert_eq!(a.string(sym), b"\x00\xFF\x00\x00");
+    /// assert_eq!(a.string_write_at(sym, 3, b"ab"), Err(HeapError::OutOfBounds));
+    /// ```
+    pub fn string_write_at(
+        &mut self,
+        ptr: u32,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Result<(), HeapError> {
+        let s = self.lookup_mut(ptr)?;
+        let dest = offset
+            .checked_add(bytes.len())
+            .and_then(|end| s.get_mut(offset..end))
+            .ok_or(HeapError::OutOfBounds)?;
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Shorten the string in the heap identified by `ptr` to `len` bytes.
+    ///
+    /// If `len` is greater than or equal to the length of the string, this
+    /// function has no effect.
+    ///
+    /// # Errors
+    ///
+    /// See [`try_string`](Self::try_string).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Artichoke".to_owned());
+    /// a.string_truncate(sym, 4).unwrap();
+    /// assert_eq!(a.string(sym), b"Arti");
+    /// a.string_truncate(sym, 10).unwrap();
+    /// assert_eq!(a.string(sym), b"Arti");
+    /// ```
+    pub fn string_truncate(&mut self, ptr: u32, len: usize) -> Result<(), HeapError> {
+        let s = self.lookup_mut(ptr)?;
+        let removed = s.len().saturating_sub(len);
+        s.truncate(len);
+        self.shrink(removed);
+        Ok(())
+    }
+
+    /// Append `bytes` to the end of the string in the heap identified by
+    /// `ptr`.
+    ///
+    /// # Errors
+    ///
+    /// See [`try_string`](Self::try_string).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// a.string_extend(sym, b"\x00\xFF").unwrap();
+    /// assert_eq!(a.string(sym), b"Wasm\x00\xFF");
+    /// ```
+    pub fn string_extend(&mut self, ptr: u32, bytes: &[u8]) -> Result<(), HeapError> {
+        let s = self.lookup_mut(ptr)?;
+        s.extend_from_slice(bytes);
+        self.grow(bytes.len());
+        Ok(())
+    }
+
+    /// Retrieve a mutable view of the byte contents of the string in the heap
+    /// identified by `ptr`.
+    ///
+    /// If `ptr` refers to a string not present in the heap, this function will
+    /// return [`None`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.reserve(4);
+    /// a.string_mut(sym).unwrap().copy_from_slice(b"Wasm");
+    /// assert_eq!(a.string(sym), b"Wasm");
+    /// assert!(a.string_mut(u32::MAX).is_none());
+    /// ```
+    #[must_use]
+    pub fn string_mut(&mut self, ptr: u32) -> Option<&mut [u8]> {
+        if let Err(err) = self.lookup(ptr) {
+            self.misuse(ptr, err);
+            return None;
+        }
+        self.lookup_mut(ptr).ok().map(Vec::as_mut_slice)
+    }
+
+    /// Retrieve a raw pointer to the byte contents of the string in the heap
+    /// identified by `ptr`.
+    ///
+    /// The returned pointer is an address in Wasm linear memory. Foreign code
+    /// may read or write up to [`string_getlen`] bytes starting at this
+    /// address, which allows copying whole buffers across the Wasm boundary at
+    /// once.
+    ///
+    /// The returned pointer is invalidated by any operation which modifies the
+    /// heap slot, such as [`string_putch`] or [`free`].
+    ///
+    /// If `ptr` refers to a string not present in the heap, this function will
+    /// return a null pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::string::Heap;
+    ///
+    /// let mut a = Heap::new();
+    /// let sym = a.allocate("Wasm".to_owned());
+    /// assert!(!a.string_as_mut_ptr(sym).is_null());
+    /// assert!(a.string_as_mut_ptr(u32::MAX).is_null());
+    /// ```
+    ///
+    /// [`string_getlen`]: Self::string_getlen
+    /// [`string_putch`]: Self::string_putch
+    /// [`free`]: Self::free
+    #[must_use]
+    pub fn string_as_mut_ptr(&mut self, ptr: u32) -> *mut u8 {
+        self.string_mut(ptr)
+            .map_or(ptr::null_mut(), <[u8]>::as_mut_ptr)
+    }
+
+    fn lookup(&self, ptr: u32) -> Result<&Vec<u8>, HeapError> {
+        let (index, generation) = decode(ptr);
+        let slot = self
+            .slots
+            .get(index as usize)
+            .ok_or(HeapError::Unallocated)?;
+        match slot.bytes {
+            Some(ref bytes) if slot.generation == generation => Ok(bytes),
+            // Generations only increase, so a handle from an older generation
+            // of this slot was returned by the heap and has been freed.
+            _ if generation < slot.generation => Err(HeapError::Stale),
+            _ => Err(HeapError::Unallocated),
+        }
+    }
+
+    fn lookup_mut(&mut self, ptr: u32) -> Result<&mut Vec<u8>, HeapError> {
+        self.lookup(ptr)?;
+        let (index, _) = decode(ptr);
+        self.slots[index as usize]
+            .bytes
+            .as_mut()
+            .ok_or(HeapError::Unallocated)
+    }
+
+    #[cfg(test)]
+    fn with_max_slots(max_slots: u32) -> Self {
+        Self {
+            max_slots,
+            ..Self::default()
+        }
+    }
+
+    fn misuse(&self, ptr: u32, err: HeapError) {
+        if self.panic_on_misuse {
+            panic!("invalid string heap handle {ptr:#010x}: {err}");
+        }
+    }
+}
+
+fn encode(index: u32, generation: u8) -> u32 {
+    (u32::from(generation) << INDEX_BITS) | index
+}
+
+fn decode(ptr: u32) -> (u32, u8) {
+    let index = ptr & INDEX_MASK;
+    let generation = (ptr >> INDEX_BITS) as u8;
+    (index, generation)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{Heap, HeapError};
+
+    /// A xorshift PRNG so churn tests are reproducible from their seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Run random allocate, free, and read operations against a heap and check
+    /// it against a model of the live strings.
+    fn churn(seed: u64, mut heap: Heap, ops: usize) {
+        let mut rng = Rng(seed);
+        let mut live = HashMap::<u32, Vec<u8>>::new();
+        let mut issued = HashSet::<u32>::new();
+        let mut order = Vec::<u32>::new();
+
+        for op in 0..ops {
+            match rng.below(3) {
+                0 | 1 if !live.is_empty() && rng.below(2) == 0 => {
+                    let ptr = order[rng.below(order.len())];
+                    if live.remove(&ptr).is_some() {
+                        heap.free(ptr);
+                    }
+                }
+                0 | 1 => {
+                    let s = format!("{seed}:{op}");
+                    match heap.try_allocate(s.clone()) {
+                        Ok(ptr) => {
+                            assert!(issued.insert(ptr), "handle {ptr:#x} was aliased");
+                            assert_ne!(ptr >> 24, 0xFF);
+                            live.insert(ptr, s.into_bytes());
+                            order.push(ptr);
+                        }
+                        Err(err) => assert_eq!(err, HeapError::Exhausted),
+                    }
+                }
+                _ if !order.is_empty() => {
+                    let ptr = order[rng.below(order.len())];
+                    match live.get(&ptr) {
+                        Some(bytes) => assert_eq!(heap.try_string(ptr), Ok(bytes.as_slice())),
+                        None => assert_eq!(heap.try_string(ptr), Err(HeapError::Stale)),
+                    }
+                }
+                _ => {}
+            }
+            assert_eq!(heap.len(), live.len());
+        }
+        for (&ptr, bytes) in &live {
+            assert_eq!(heap.try_string(ptr), Ok(bytes.as_slice()));
+        }
+    }
+
+    #[test]
+    fn churn_never_aliases_handles() {
+        for seed in 1..=32 {
+            churn(seed, Heap::new(), 2_000);
+        }
+    }
+
+    #[test]
+    fn churn_through_exhaustion_never_aliases_handles() {
+        for seed in 1..=32 {
+            churn(seed, Heap::with_max_slots(4), 5_000);
+        }
+    }
+
+    #[test]
+    fn stats_track_bytes_and_peaks() {
+        let mut heap = Heap::new();
+        let a = heap.allocate(String::from("abc"));
+        let b = heap.reserve(5);
+        heap.string_putch(a, b'd');
+        let stats = heap.stats();
+        assert_eq!((stats.live, stats.bytes), (2, 9));
+
+        heap.free(b);
+        heap.set_tag(a, "test");
+        let stats = heap.stats();
+        assert_eq!((stats.live, stats.bytes), (1, 4));
+        assert_eq!((stats.peak_live, stats.peak_bytes), (2, 9));
+
+        let mut s = String::new();
+        heap.live_slots()[0].write_json(&mut s).unwrap();
+        assert_eq!(s, r#"{"ptr":0,"len":4,"tag":"test"}"#);
+
+        heap.clear();
+        assert_eq!(heap.stats().bytes, 0);
+        let c = heap.allocate(String::new());
+        assert_eq!(heap.live_slots()[0].tag, None);
+        assert_ne!(c, a);
+    }
+
+    #[test]
+    fn byte_range_operations_track_stats() {
+        let mut heap = Heap::new();
+        let sym = heap.allocate_bytes(vec![0xFF, 0x00, 0x7F]);
+        heap.string_extend(sym, &[0x80; 5]).unwrap();
+        assert_eq!(heap.stats().bytes, 8);
+        heap.string_write_at(sym, 6, b"ab").unwrap();
+        assert_eq!(heap.string_slice(sym, 5, 3), Ok(&b"\x80ab"[..]));
+        heap.string_truncate(sym, 2).unwrap();
+        assert_eq!(heap.string(sym), b"\xFF\x00");
+        assert_eq!(heap.stats().bytes, 2);
+        assert_eq!(heap.stats().peak_bytes, 8);
+
+        assert_eq!(
+            heap.string_slice(sym, usize::MAX, 2),
+            Err(HeapError::OutOfBounds)
+        );
+        heap.free(sym);
+        assert_eq!(heap.string_extend(sym, b"x"), Err(HeapError::Stale));
+        assert_eq!(heap.string_truncate(sym, 0), Err(HeapError::Stale));
+        assert_eq!(heap.stats().bytes, 0);
+    }
+
+    #[test]
+    fn freed_slots_are_reused() {
+        let mut heap = Heap::new();
+        let first = heap.allocate(String::from("a"));
+        heap.free(first);
+        let second = heap.allocate(String::from("b"));
+        assert_eq!(second & 0x00FF_FFFF, first & 0x00FF_FFFF);
+        assert_ne!(second, first);
+        assert_eq!(heap.try_string(first), Err(HeapError::Stale));
+        assert_eq!(heap.string(second), b"b");
+    }
+
+    #[test]
+    fn live_slots_exhaust_heap() {
+        let mut heap = Heap::with_max_slots(2);
+        let first = heap.try_allocate(String::new()).unwrap();
+        let _second = heap.try_reserve(4).unwrap();
+        assert_eq!(heap.try_allocate(String::new()), Err(HeapError::Exhausted));
+
+        heap.free(first);
+        assert!(heap.try_allocate(String::new()).is_ok());
+    }
+
+    #[test]
+    fn exhausted_generations_retire_slots() {
+        let mut heap = Heap::with_max_slots(2);
+        let mut allocations = 0;
+        while let Ok(ptr) = heap.try_allocate(String::new()) {
+            heap.free(ptr);
+            allocations += 1;
+        }
+        // Generations `0` through `254` are issued for each slot.
+        assert_eq!(allocations, 2 * 255);
+        assert!(heap.is_empty());
+        assert_eq!(heap.try_allocate(String::new()), Err(HeapError::Exhausted));
+    }
+
+    #[test]
+    fn first_allocation_is_handle_zero() {
+        let mut heap = Heap::new();
+        assert_eq!(heap.allocate(String::from("build info")), 0);
+    }
+
+    #[test]
+    fn freed_handles_are_stale() {
+        let mut heap = Heap::new();
+        let sym = heap.allocate(String::from("Wasm"));
+        heap.free(sym);
+        assert_eq!(heap.try_string(sym), Err(HeapError::Stale));
+        assert_eq!(heap.try_string_getlen(sym), Err(HeapError::Stale));
+        assert_eq!(heap.try_string_getch(sym, 0), Err(HeapError::Stale));
+        assert_eq!(heap.string(sym), b"");
+        assert!(heap.is_empty());
+
+        let sym = heap.allocate(String::from("Wasm"));
+        heap.clear();
+        assert_eq!(heap.try_string(sym), Err(HeapError::Stale));
+    }
+
+    #[test]
+    fn invalid_reads_are_empty() {
+        let mut heap = Heap::new();
+        let sym = heap.allocate(String::from("abc"));
+        assert_eq!(heap.string_getlen(sym), 3);
+        assert_eq!(heap.string_getch(sym, 3), 0);
+
+        heap.free(sym);
+        assert_eq!(heap.string_getlen(sym), 0);
+        assert_eq!(heap.string_getch(sym, 0), 0);
+        // Freeing a slot twice is a no-op.
+        heap.free(sym);
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn binary_bytes_survive_byte_range_operations() {
+        let mut heap = Heap::new();
+        let sym = heap.allocate(String::new());
+        for byte in [0x80, 0x00, 0xFE] {
+            heap.string_putch(sym, byte);
+        }
+        let tail = heap.string_slice(sym, 1, 2).unwrap().to_vec();
+        assert_eq!(tail, [0x00, 0xFE]);
+        heap.string_extend(sym, &tail).unwrap();
+        heap.string_write_at(sym, 0, &tail).unwrap();
+        heap.string_truncate(sym, 4).unwrap();
+        assert_eq!(heap.string(sym), [0x00, 0xFE, 0xFE, 0x00]);
+    }
+
+    #[test]
+    fn forged_handles_are_unallocated() {
+        let mut heap = Heap::new();
+        let sym = heap.allocate(String::from("Wasm"));
+        assert_eq!(heap.try_string(sym + 1), Err(HeapError::Unallocated));
+        assert_eq!(
+            heap.try_string(sym | (1 << 24)),
+            Err(HeapError::Unallocated)
+        );
+        assert_eq!(heap.try_string(u32::MAX), Err(HeapError::Unallocated));
+        assert_eq!(heap.try_string(sym), Ok(&b"Wasm"[..]));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "invalid string heap handle 0x00000000: handle refers to a freed string"
+    )]
+    fn double_free_panics_on_misuse() {
+        let mut heap = Heap::new();
+        heap.set_panic_on_misuse(true);
+        let sym = heap.allocate(String::from("Wasm"));
+        heap.free(sym);
+        heap.free(sym);
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds")]
+    fn out_of_bounds_read_panics_on_misuse() {
+        let mut heap = Heap::new();
+        heap.set_panic_on_misuse(true);
+        let sym = heap.allocate(String::from("Wasm"));
+        let _ = heap.string_getch(sym, 4);
+    }
 }
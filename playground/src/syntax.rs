@@ -0,0 +1,230 @@
+//! Syntax checking without evaluation.
+//!
+//! The playground editor uses [`check`] to underline parse errors while the
+//! user is typing. Source is run through the mruby parser with error capture
+//! enabled and is never compiled or executed.
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+
+use artichoke::backend::ffi::InterpreterExtractError;
+use artichoke::backend::sys;
+use artichoke::prelude::*;
+
+use crate::json;
+
+/// Severity of a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The source failed to parse.
+    Error,
+    /// The source parsed, but the parser flagged suspicious code.
+    Warning,
+}
+
+impl Severity {
+    /// The name of this severity.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::syntax::Severity;
+    ///
+    /// assert_eq!(Severity::Error.name(), "error");
+    /// assert_eq!(Severity::Warning.name(), "warning");
+    /// ```
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// A message from the parser attached to a position in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The 1-based line the diagnostic refers to.
+    pub line: u32,
+    /// The 0-based column the diagnostic refers to, counted in bytes.
+    ///
+    /// The parser reports the column it had read up to when it found the
+    /// problem, which is usually just past the offending token. Editors which
+    /// number columns from 1 should add one.
+    pub column: u32,
+    /// Whether the diagnostic is an error or a warning.
+    pub severity: Severity,
+    /// The parser's description of the problem.
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Serialize this diagnostic as a JSON object.
+    ///
+    /// The object has the following shape:
+    ///
+    /// ```json
+    /// { "line": 1, "column": 4, "severity": "error", "message": "..." }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the provided writer returns an error, this function will return it.
+    pub fn write_json<W>(&self, mut f: W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        write!(
+            f,
+            r#"{{"line":{},"column":{},"severity":"{}","message":"#,
+            self.line,
+            self.column,
+            self.severity.name()
+        )?;
+        json::write_string(&mut f, self.message.as_bytes())?;
+        f.write_str("}")
+    }
+}
+
+/// Serialize a list of diagnostics as a JSON array.
+///
+/// # Errors
+///
+/// If the provided writer returns an error, this function will return it.
+pub fn write_json<W>(mut f: W, diagnostics: &[Diagnostic]) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    f.write_str("[")?;
+    for (idx, diagnostic) in diagnostics.iter().enumerate() {
+        if idx > 0 {
+            f.write_str(",")?;
+        }
+        diagnostic.write_json(&mut f)?;
+    }
+    f.write_str("]")
+}
+
+/// Parse `code` on the given interpreter without evaluating it and return the
+/// errors and warnings reported by the parser.
+///
+/// Positions are reported relative to a file named [`REPL_FILENAME`], the same
+/// context used for playground evals.
+///
+/// An empty list means the source is syntactically valid.
+///
+/// # Errors
+///
+/// If the interpreter cannot be accessed, an error is returned.
+///
+/// [`REPL_FILENAME`]: crate::REPL_FILENAME
+pub fn check(interp: &mut Artichoke, code: &[u8]) -> Result<Vec<Diagnostic>, Error> {
+    let filename =
+        CString::new(crate::REPL_FILENAME).map_err(|_| InterpreterExtractError::new())?;
+    // SAFETY: The parser context and parser state are created and freed within
+    // this closure and are never exposed to the VM, so no Ruby code runs while
+    // they are live.
+    let diagnostics = unsafe {
+        interp.with_ffi_boundary(|mrb| {
+            let context = sys::mrbc_context_new(mrb);
+            if context.is_null() {
+                return None;
+            }
+            sys::mrbc_filename(mrb, context, filename.as_ptr());
+            (*context).set_capture_errors(true);
+
+            let parser = sys::mrb_parse_nstring(mrb, code.as_ptr().cast(), code.len(), context);
+            let diagnostics = if parser.is_null() {
+                None
+            } else {
+                let parser_state = &*parser;
+                let mut diagnostics = Vec::new();
+                let errors = parser_state.error_buffer.iter().take(parser_state.nerr);
+                let warnings = parser_state.warn_buffer.iter().take(parser_state.nwarn);
+                let messages = errors
+                    .map(|message| (Severity::Error, message))
+                    .chain(warnings.map(|message| (Severity::Warning, message)));
+                for (severity, message) in messages {
+                    let text = if message.message.is_null() {
+                        String::from("syntax error")
+                    } else {
+                        CStr::from_ptr(message.message)
+                            .to_string_lossy()
+                            .into_owned()
+                    };
+                    diagnostics.push(Diagnostic {
+                        line: message.lineno.into(),
+                        column: u32::try_from(message.column).unwrap_or_default(),
+                        severity,
+                        message: text,
+                    });
+                }
+                sys::mrb_parser_free(parser);
+                Some(diagnostics)
+            };
+            sys::mrbc_context_free(mrb, context);
+            diagnostics
+        })?
+    };
+    let diagnostics = diagnostics.ok_or_else(InterpreterExtractError::new)?;
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use artichoke::prelude::*;
+
+    use super::{check, write_json, Diagnostic, Severity};
+
+    #[test]
+    fn valid_source_has_no_diagnostics() {
+        let mut interp = artichoke::interpreter().unwrap();
+        let diagnostics = check(&mut interp, b"def add(a, b)\n  a + b\nend\nadd(1, 2)\n").unwrap();
+        assert!(diagnostics.is_empty());
+        interp.close();
+    }
+
+    #[test]
+    fn parse_error_is_reported_with_position() {
+        let mut interp = artichoke::interpreter().unwrap();
+        let diagnostics = check(&mut interp, b"x = 1\ny = (2 +\n").unwrap();
+        let error = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.severity == Severity::Error)
+            .unwrap();
+        assert!(error.line >= 2);
+        assert!(!error.message.is_empty());
+        interp.close();
+    }
+
+    #[test]
+    fn check_does_not_evaluate() {
+        let mut interp = artichoke::interpreter().unwrap();
+        let diagnostics = check(&mut interp, b"$checked = true").unwrap();
+        assert!(diagnostics.is_empty());
+        let checked = interp.eval(b"$checked").unwrap();
+        assert!(checked.is_nil());
+        interp.close();
+    }
+
+    #[test]
+    fn diagnostics_json() {
+        let diagnostics = [Diagnostic {
+            line: 2,
+            column: 7,
+            severity: Severity::Error,
+            message: String::from("syntax error, unexpected end of file"),
+        }];
+        let mut s = String::new();
+        write_json(&mut s, &diagnostics).unwrap();
+        assert_eq!(
+            s,
+            r#"[{"line":2,"column":7,"severity":"error","message":"syntax error, unexpected end of file"}]"#
+        );
+
+        let mut s = String::new();
+        write_json(&mut s, &[]).unwrap();
+        assert_eq!(s, "[]");
+    }
+}
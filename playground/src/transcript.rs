@@ -0,0 +1,206 @@
+//! Ordered transcripts of interleaved stdout and stderr.
+//!
+//! Artichoke's capturing output strategy buffers stdout and stderr separately,
+//! which loses the order in which they were written. The playground installs a
+//! small Ruby prelude which wraps `Kernel#print`, `puts`, `p`, and `warn` to
+//! record the stream and bytes of each write.
+//!
+//! The recorded transcript is only trusted if it accounts for exactly the
+//! bytes in the capture buffers. Output written some other way, for example by
+//! a method the prelude does not wrap, falls back to all of stdout followed by
+//! all of stderr.
+
+use artichoke::backend::value;
+use artichoke::prelude::*;
+
+use crate::output::{Chunk, Stream};
+use crate::prelude::Prelude;
+
+/// Ruby source for the transcript prelude.
+const PRELUDE: Prelude = Prelude {
+    path: "playground/transcript.rb",
+    source: br##"
+module Playground
+  TRANSCRIPT = []
+
+  module Transcript
+    @depth = 0
+
+    def self.record(stream, string)
+      depth = @depth
+      @depth += 1
+      TRANSCRIPT << [stream, string] if depth.zero? && !string.empty?
+      yield
+    ensure
+      @depth -= 1
+    end
+
+    def self.puts_string(args)
+      return "\n" if args.empty?
+
+      args.flatten.map do |arg|
+        line = arg.to_s
+        line.end_with?("\n") ? line : "#{line}\n"
+      end.join
+    end
+  end
+end
+
+module Kernel
+  alias_method :__playground_print, :print
+  alias_method :__playground_puts, :puts
+  alias_method :__playground_p, :p
+  alias_method :__playground_warn, :warn
+
+  def print(*args)
+    string = args.map(&:to_s).join
+    Playground::Transcript.record('stdout', string) { __playground_print(*args) }
+  end
+
+  def puts(*args)
+    string = Playground::Transcript.puts_string(args)
+    Playground::Transcript.record('stdout', string) { __playground_puts(*args) }
+  end
+
+  def p(*args)
+    string = args.map { |arg| "#{arg.inspect}\n" }.join
+    Playground::Transcript.record('stdout', string) { __playground_p(*args) }
+  end
+
+  def warn(*msgs)
+    string = msgs.empty? ? '' : Playground::Transcript.puts_string(msgs)
+    Playground::Transcript.record('stderr', string) { __playground_warn(*msgs) }
+  end
+end
+"##,
+};
+
+/// Install the transcript prelude on the given interpreter and discard any
+/// previously recorded writes.
+///
+/// # Errors
+///
+/// If the prelude fails to load, an error is returned.
+pub fn reset(interp: &mut Artichoke) -> Result<(), Error> {
+    PRELUDE.load(interp)?;
+    let transcript = interp.eval(b"Playground::TRANSCRIPT")?;
+    transcript.funcall(interp, "clear", &[], None)?;
+    Ok(())
+}
+
+/// Build the ordered transcript of the given captured output.
+///
+/// If the writes recorded by the prelude do not account for exactly the bytes
+/// in `stdout` and `stderr`, the transcript is all of stdout followed by all
+/// of stderr.
+pub fn collect(interp: &mut Artichoke, stdout: &[u8], stderr: &[u8]) -> Vec<Chunk> {
+    recorded(interp)
+        .ok()
+        .filter(|chunks| concat(chunks, Stream::Stdout) == stdout)
+        .filter(|chunks| concat(chunks, Stream::Stderr) == stderr)
+        .unwrap_or_else(|| grouped(stdout, stderr))
+}
+
+/// The transcript of all of `stdout` followed by all of `stderr`.
+#[must_use]
+pub fn grouped(stdout: &[u8], stderr: &[u8]) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    append(&mut chunks, Stream::Stdout, stdout);
+    append(&mut chunks, Stream::Stderr, stderr);
+    chunks
+}
+
+fn recorded(interp: &mut Artichoke) -> Result<Vec<Chunk>, Error> {
+    let transcript = interp.eval(b"Playground::TRANSCRIPT")?;
+    let entries: Vec<value::Value> = interp.try_convert_mut(transcript)?;
+
+    let mut chunks = Vec::new();
+    for entry in entries {
+        let stream = entry.funcall(interp, "first", &[], None)?;
+        let bytes = entry.funcall(interp, "last", &[], None)?;
+        let stream = match stream.try_convert_into_mut::<String>(interp)?.as_str() {
+            "stderr" => Stream::Stderr,
+            _ => Stream::Stdout,
+        };
+        let bytes = bytes.try_convert_into_mut::<Vec<u8>>(interp)?;
+        append(&mut chunks, stream, &bytes);
+    }
+    Ok(chunks)
+}
+
+/// Append bytes to the transcript, merging consecutive writes to the same
+/// stream into a single chunk.
+fn append(chunks: &mut Vec<Chunk>, stream: Stream, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    match chunks.last_mut() {
+        Some(last) if last.stream == stream => last.bytes.extend_from_slice(bytes),
+        _ => chunks.push(Chunk {
+            stream,
+            bytes: bytes.to_vec(),
+        }),
+    }
+}
+
+fn concat(chunks: &[Chunk], stream: Stream) -> Vec<u8> {
+    chunks
+        .iter()
+        .filter(|chunk| chunk.stream == stream)
+        .flat_map(|chunk| chunk.bytes.iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use artichoke::prelude::*;
+
+    use super::{collect, grouped, reset};
+    use crate::output::Stream;
+
+    #[test]
+    fn grouped_skips_empty_streams() {
+        let chunks = grouped(b"out\n", b"");
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].stream, Stream::Stdout);
+    }
+
+    #[test]
+    fn records_interleaved_writes() {
+        let mut interp = artichoke::interpreter().unwrap();
+        reset(&mut interp).unwrap();
+        interp
+            .eval(b"puts 'a'; warn 'b'; print 'c', 1; p :d; warn 'e'")
+            .unwrap();
+
+        let state = interp.state.as_mut().unwrap();
+        let stdout = state.output.stdout().to_vec();
+        let stderr = state.output.stderr().to_vec();
+        let chunks = collect(&mut interp, &stdout, &stderr);
+        let chunks = chunks
+            .iter()
+            .map(|chunk| (chunk.stream, chunk.bytes.as_slice()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            [
+                (Stream::Stdout, &b"a\n"[..]),
+                (Stream::Stderr, &b"b\n"[..]),
+                (Stream::Stdout, &b"c1:d\n"[..]),
+                (Stream::Stderr, &b"e\n"[..]),
+            ]
+        );
+        interp.close();
+    }
+
+    #[test]
+    fn mismatched_transcript_falls_back_to_grouped() {
+        let mut interp = artichoke::interpreter().unwrap();
+        reset(&mut interp).unwrap();
+        interp.eval(b"warn 'b'").unwrap();
+
+        let chunks = collect(&mut interp, b"a\n", b"b\n");
+        assert_eq!(chunks, grouped(b"a\n", b"b\n"));
+        interp.close();
+    }
+}
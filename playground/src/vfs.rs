@@ -0,0 +1,373 @@
+//! In-memory virtual filesystem for multi-file playground projects.
+//!
+//! A [`FileSystem`] is owned by the FFI state and mounted on an interpreter
+//! before each eval. Mounted files are:
+//!
+//! - Defined on Artichoke's virtual load path so `require` and
+//!   `require_relative` resolve against them.
+//! - Readable and writable from Ruby with `File.read`, `File.write`,
+//!   `File.exist?`, and `File.delete`.
+//!
+//! Files written or deleted from Ruby are synced back to the [`FileSystem`]
+//! once the eval completes.
+//!
+//! The `playground/` directory holds the Ruby preludes which implement
+//! playground features and is reserved. Files cannot be written there.
+//!
+//! The load path is not cleared between evals on a long-lived session
+//! interpreter. A file deleted from the [`FileSystem`] or with `File.delete`
+//! can still be loaded with `require`, and a file which has already been
+//! required is not reloaded when its contents change, matching `require` on
+//! a real filesystem. Reset the session to start from a clean load path.
+
+use std::collections::BTreeMap;
+
+use artichoke::backend::value;
+use artichoke::prelude::*;
+
+use crate::prelude::{self, Prelude};
+
+/// Ruby source for the filesystem prelude.
+const PRELUDE: Prelude = Prelude {
+    path: "playground/fs.rb",
+    source: br#"
+module Playground
+  # Contents of the files in the playground virtual filesystem keyed by path.
+  FILES = {}
+
+  def self.normalize_path(path)
+    path = path.to_s
+    path = path[2..-1] while path.start_with?('./')
+    path
+  end
+
+  def self.reserved?(path)
+    path.start_with?('playground/')
+  end
+
+  def self.eacces(path)
+    error = defined?(Errno::EACCES) ? Errno::EACCES : IOError
+    raise error, "Permission denied @ rb_sysopen - #{path}"
+  end
+
+  def self.enoent(path)
+    error = defined?(Errno::ENOENT) ? Errno::ENOENT : IOError
+    raise error, "No such file or directory @ rb_sysopen - #{path}"
+  end
+end
+
+class File
+  class << self
+    def read(path)
+      contents = Playground::FILES[Playground.normalize_path(path)]
+      Playground.enoent(path) if contents.nil?
+      contents.dup
+    end
+
+    def write(path, string)
+      key = Playground.normalize_path(path)
+      Playground.eacces(path) if Playground.reserved?(key)
+      string = string.to_s
+      Playground::FILES[key] = string.dup
+      string.bytesize
+    end
+
+    def exist?(path)
+      Playground::FILES.key?(Playground.normalize_path(path))
+    end
+    alias file? exist?
+
+    def delete(*paths)
+      paths.each do |path|
+        removed = Playground::FILES.delete(Playground.normalize_path(path))
+        Playground.enoent(path) if removed.nil?
+      end
+      paths.length
+    end
+    alias unlink delete
+  end
+end
+"#,
+};
+
+/// An in-memory filesystem of byte contents keyed by relative path.
+///
+/// Paths are relative to the root of the playground project. A leading `./`
+/// is stripped. Absolute paths, empty path segments, `..` segments, and paths
+/// in the reserved `playground/` directory are rejected.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct FileSystem {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl FileSystem {
+    /// Construct a new, empty filesystem.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::vfs::FileSystem;
+    ///
+    /// let fs = FileSystem::new();
+    /// assert!(fs.is_empty());
+    /// ```
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of files in the filesystem.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns `true` if the filesystem contains no files.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Normalize a path to the form used as a key in the filesystem.
+    ///
+    /// Returns [`None`] if the path is not a valid relative path or is in the
+    /// reserved `playground/` directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::vfs::FileSystem;
+    ///
+    /// assert_eq!(FileSystem::normalize("./lib/helper.rb"), Some("lib/helper.rb"));
+    /// assert_eq!(FileSystem::normalize("/etc/passwd"), None);
+    /// assert_eq!(FileSystem::normalize("../main.rb"), None);
+    /// assert_eq!(FileSystem::normalize("playground/stdin.rb"), None);
+    /// assert_eq!(FileSystem::normalize(""), None);
+    /// ```
+    #[must_use]
+    pub fn normalize(mut path: &str) -> Option<&str> {
+        while let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        }
+        if path.contains('\0') {
+            return None;
+        }
+        let is_valid = path
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+        let is_reserved = path.starts_with(prelude::DIR);
+        (is_valid && !is_reserved).then_some(path)
+    }
+
+    /// Create or replace the file at `path`.
+    ///
+    /// Returns [`None`] if `path` is not a valid relative path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::vfs::FileSystem;
+    ///
+    /// let mut fs = FileSystem::new();
+    /// assert!(fs.write("main.rb", b"puts 1".to_vec()).is_some());
+    /// assert!(fs.write("/main.rb", b"puts 1".to_vec()).is_none());
+    /// assert_eq!(fs.read("./main.rb"), Some(&b"puts 1"[..]));
+    /// ```
+    pub fn write(&mut self, path: &str, contents: Vec<u8>) -> Option<()> {
+        let path = Self::normalize(path)?;
+        self.files.insert(path.to_owned(), contents);
+        Some(())
+    }
+
+    /// Retrieve the contents of the file at `path`.
+    ///
+    /// Returns [`None`] if there is no file at `path`.
+    #[must_use]
+    pub fn read(&self, path: &str) -> Option<&[u8]> {
+        let path = Self::normalize(path)?;
+        self.files.get(path).map(Vec::as_slice)
+    }
+
+    /// Delete the file at `path` and return its contents.
+    ///
+    /// Returns [`None`] if there is no file at `path`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use playground::vfs::FileSystem;
+    ///
+    /// let mut fs = FileSystem::new();
+    /// fs.write("main.rb", b"puts 1".to_vec());
+    /// assert_eq!(fs.remove("main.rb"), Some(b"puts 1".to_vec()));
+    /// assert_eq!(fs.remove("main.rb"), None);
+    /// ```
+    pub fn remove(&mut self, path: &str) -> Option<Vec<u8>> {
+        let path = Self::normalize(path)?;
+        self.files.remove(path)
+    }
+
+    /// Iterate over the paths of every file in the filesystem in sorted order.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// Iterate over every file in the filesystem in sorted path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.files
+            .iter()
+            .map(|(path, contents)| (path.as_str(), contents.as_slice()))
+    }
+
+    /// Remove every file from the filesystem.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+/// Install the filesystem prelude on the given interpreter and expose the
+/// contents of `fs` to `require`, `require_relative`, and `File`.
+///
+/// # Errors
+///
+/// If the prelude fails to load or a file cannot be defined on the load path,
+/// an error is returned.
+pub fn mount(interp: &mut Artichoke, fs: &FileSystem) -> Result<(), Error> {
+    PRELUDE.load(interp)?;
+
+    let files = interp.eval(b"Playground::FILES")?;
+    files.funcall(interp, "clear", &[], None)?;
+    for (path, contents) in fs.iter() {
+        interp.def_rb_source_file(path, contents.to_vec())?;
+
+        let key = interp.convert_mut(path);
+        let value = interp.convert_mut(contents);
+        files.funcall(interp, "[]=", &[key, value], None)?;
+    }
+    Ok(())
+}
+
+/// Read back the files visible to `File` on the given interpreter, which
+/// includes any writes and deletes made during the eval.
+///
+/// Files whose paths are not valid UTF-8 or not valid relative paths are
+/// skipped, so one bad write does not discard the rest.
+///
+/// # Errors
+///
+/// If the contents of the filesystem cannot be extracted from the
+/// interpreter, an error is returned.
+pub fn snapshot(interp: &mut Artichoke) -> Result<FileSystem, Error> {
+    let files = interp.eval(b"Playground::FILES")?;
+    let paths = files.funcall(interp, "keys", &[], None)?;
+    let paths: Vec<value::Value> = interp.try_convert_mut(paths)?;
+
+    let mut fs = FileSystem::new();
+    for path in paths {
+        let contents = files.funcall(interp, "[]", &[path], None)?;
+        let path = match path.try_convert_into_mut::<String>(interp) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        let contents = match contents.try_convert_into_mut::<Vec<u8>>(interp) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+        fs.write(&path, contents);
+    }
+    Ok(fs)
+}
+
+#[cfg(test)]
+mod tests {
+    use artichoke::prelude::*;
+
+    use super::{mount, snapshot, FileSystem};
+
+    #[test]
+    fn normalize_relative_paths() {
+        assert_eq!(FileSystem::normalize("main.rb"), Some("main.rb"));
+        assert_eq!(FileSystem::normalize("./main.rb"), Some("main.rb"));
+        assert_eq!(FileSystem::normalize("././lib/a.rb"), Some("lib/a.rb"));
+    }
+
+    #[test]
+    fn reject_invalid_paths() {
+        assert_eq!(FileSystem::normalize(""), None);
+        assert_eq!(FileSystem::normalize("./"), None);
+        assert_eq!(FileSystem::normalize("/main.rb"), None);
+        assert_eq!(FileSystem::normalize("lib//a.rb"), None);
+        assert_eq!(FileSystem::normalize("lib/./a.rb"), None);
+        assert_eq!(FileSystem::normalize("lib/../a.rb"), None);
+        assert_eq!(FileSystem::normalize("lib/"), None);
+        assert_eq!(FileSystem::normalize("main\0.rb"), None);
+    }
+
+    #[test]
+    fn reject_reserved_paths() {
+        assert_eq!(FileSystem::normalize("playground/fs.rb"), None);
+        assert_eq!(FileSystem::normalize("./playground/lib/a.rb"), None);
+        assert_eq!(
+            FileSystem::normalize("playground.rb"),
+            Some("playground.rb")
+        );
+        assert_eq!(
+            FileSystem::normalize("lib/playground/a.rb"),
+            Some("lib/playground/a.rb")
+        );
+
+        let mut fs = FileSystem::new();
+        assert!(fs
+            .write("playground/fs.rb", b"raise 'shadowed'".to_vec())
+            .is_none());
+
+        let mut interp = artichoke::interpreter().unwrap();
+        mount(&mut interp, &fs).unwrap();
+        let err = interp
+            .eval(b"File.write('./playground/fs.rb', 'raise')")
+            .unwrap_err();
+        assert!(
+            matches!(&*err.name(), "Errno::EACCES" | "IOError"),
+            "{}",
+            err.name()
+        );
+        let value = interp.eval(b"File.exist?('playground/fs.rb')").unwrap();
+        assert_eq!(value.inspect(&mut interp), b"false");
+        assert!(snapshot(&mut interp).unwrap().is_empty());
+        interp.close();
+    }
+
+    #[test]
+    fn write_read_remove() {
+        let mut fs = FileSystem::new();
+        fs.write("lib/helper.rb", b"X = 1".to_vec()).unwrap();
+        fs.write("main.rb", b"require_relative 'lib/helper'".to_vec())
+            .unwrap();
+        assert_eq!(fs.len(), 2);
+        assert_eq!(fs.paths().collect::<Vec<_>>(), ["lib/helper.rb", "main.rb"]);
+
+        fs.write("./main.rb", b"p X".to_vec()).unwrap();
+        assert_eq!(fs.read("main.rb"), Some(&b"p X"[..]));
+        assert_eq!(fs.len(), 2);
+
+        assert!(fs.remove("lib/helper.rb").is_some());
+        assert!(fs.read("lib/helper.rb").is_none());
+        assert_eq!(fs.len(), 1);
+    }
+
+    #[test]
+    fn snapshot_skips_invalid_paths() {
+        let mut interp = artichoke::interpreter().unwrap();
+        let mut fs = FileSystem::new();
+        fs.write("main.rb", b"p 1".to_vec()).unwrap();
+        mount(&mut interp, &fs).unwrap();
+
+        interp.eval(b"File.write('out.txt', 'ok')").unwrap();
+        interp.eval(b"File.write(\"\\xFF.txt\", 'bad')").unwrap();
+        interp.eval(b"File.write('/abs.txt', 'bad')").unwrap();
+        let synced = snapshot(&mut interp).unwrap();
+        assert_eq!(synced.paths().collect::<Vec<_>>(), ["main.rb", "out.txt"]);
+        assert_eq!(synced.read("out.txt"), Some(&b"ok"[..]));
+        interp.close();
+    }
+}
@@ -14,6 +14,8 @@ module Artichoke
         -C link-arg=-sWASMFS=1
         -C link-arg=-sENVIRONMENT=web
         -C link-arg=-sSUPPORT_LONGJMP=1
+        -C link-arg=-sALLOW_TABLE_GROWTH=1
+        -C link-arg=-sEXPORTED_RUNTIME_METHODS=addFunction
       ].freeze
 
       # Disable certain warnings for code patterns that are contained in
@@ -12,12 +12,22 @@ declare namespace Module {
   export type StringPointer = number & { _opaque: typeof STRING_POINTER_TYPE };
 
   export class Ffi {
+    public addFunction(
+      func: (fd: number, bytes: number, len: number) => void,
+      signature: "viii",
+    ): number;
+
     public _artichoke_web_repl_init(): Artichoke;
+    public _artichoke_web_repl_free(state: Artichoke): number;
 
     public _artichoke_string_getlen(
       state: Artichoke,
       ptr: StringPointer,
     ): number;
+    public _artichoke_string_try_getlen(
+      state: Artichoke,
+      ptr: StringPointer,
+    ): number;
     public _artichoke_string_getch(
       state: Artichoke,
       ptr: StringPointer,
@@ -28,13 +38,140 @@ declare namespace Module {
       state: Artichoke,
       ptr: StringPointer,
       byte: number,
-    ): void;
-    public _artichoke_string_free(state: Artichoke, ptr: StringPointer): void;
+    ): number;
+    public _artichoke_string_free(state: Artichoke, ptr: StringPointer): number;
+    public _artichoke_string_reserve(
+      state: Artichoke,
+      len: number,
+    ): StringPointer;
+    public _artichoke_string_ptr(state: Artichoke, ptr: StringPointer): number;
+    public _artichoke_string_slice(
+      state: Artichoke,
+      ptr: StringPointer,
+      offset: number,
+      len: number,
+    ): StringPointer;
+    public _artichoke_string_write_at(
+      state: Artichoke,
+      ptr: StringPointer,
+      offset: number,
+      src: StringPointer,
+    ): number;
+    public _artichoke_string_truncate(
+      state: Artichoke,
+      ptr: StringPointer,
+      len: number,
+    ): number;
+    public _artichoke_string_extend(
+      state: Artichoke,
+      ptr: StringPointer,
+      src: StringPointer,
+    ): number;
 
     public _artichoke_eval(
       state: Artichoke,
       codeptr: StringPointer,
     ): StringPointer;
+
+    public _artichoke_eval_json(
+      state: Artichoke,
+      codeptr: StringPointer,
+    ): StringPointer;
+
+    public _artichoke_annotate(
+      state: Artichoke,
+      codeptr: StringPointer,
+    ): StringPointer;
+    public _artichoke_session_annotate(
+      state: Artichoke,
+      codeptr: StringPointer,
+    ): StringPointer;
+    public _artichoke_check_syntax(
+      state: Artichoke,
+      codeptr: StringPointer,
+    ): StringPointer;
+
+    public _artichoke_set_stdin(
+      state: Artichoke,
+      stdinptr: StringPointer,
+    ): number;
+
+    public _artichoke_argv_push(
+      state: Artichoke,
+      argptr: StringPointer,
+    ): number;
+    public _artichoke_env_set(
+      state: Artichoke,
+      nameptr: StringPointer,
+      valueptr: StringPointer,
+    ): number;
+
+    public _artichoke_set_time_limit(state: Artichoke, millis: number): number;
+
+    public _artichoke_set_memory_limit(state: Artichoke, bytes: number): number;
+
+    public _artichoke_set_determinism(
+      state: Artichoke,
+      enabled: number,
+      seed: number,
+      now: number,
+    ): number;
+
+    public _artichoke_set_heap_debug(state: Artichoke, enabled: number): number;
+
+    public _artichoke_set_output_callback(
+      state: Artichoke,
+      callback: number,
+    ): number;
+    public _artichoke_set_output_interleaved(
+      state: Artichoke,
+      enabled: number,
+    ): number;
+    public _artichoke_set_binary_format(
+      state: Artichoke,
+      format: number,
+    ): number;
+
+    public _artichoke_session_eval(
+      state: Artichoke,
+      codeptr: StringPointer,
+    ): StringPointer;
+    public _artichoke_session_reset(state: Artichoke): number;
+
+    public _artichoke_eval_at(
+      state: Artichoke,
+      codeptr: StringPointer,
+      fileptr: StringPointer,
+      line: number,
+    ): StringPointer;
+    public _artichoke_session_eval_at(
+      state: Artichoke,
+      codeptr: StringPointer,
+      fileptr: StringPointer,
+      line: number,
+    ): StringPointer;
+
+    public _artichoke_eval_file(
+      state: Artichoke,
+      pathptr: StringPointer,
+    ): StringPointer;
+    public _artichoke_fs_write(
+      state: Artichoke,
+      pathptr: StringPointer,
+      contentsptr: StringPointer,
+    ): number;
+    public _artichoke_fs_read(
+      state: Artichoke,
+      pathptr: StringPointer,
+    ): StringPointer;
+    public _artichoke_fs_delete(
+      state: Artichoke,
+      pathptr: StringPointer,
+    ): number;
+    public _artichoke_fs_list(state: Artichoke): StringPointer;
+
+    public _artichoke_heap_stats(state: Artichoke): StringPointer;
+    public _artichoke_heap_leaks(state: Artichoke): StringPointer;
   }
 }
 